@@ -0,0 +1,128 @@
+use socket::{Msg, Payload};
+use std::io::{self, ErrorKind};
+use std::str;
+
+// Message types, from linux/audit.h
+/// Get status
+pub const AUDIT_GET: u16 = 1000;
+/// Set status (enable/disable/auditd)
+pub const AUDIT_SET: u16 = 1001;
+/// Message from userspace, deprecated
+pub const AUDIT_USER: u16 = 1005;
+/// Define the login id and information
+pub const AUDIT_LOGIN: u16 = 1006;
+/// Get info about sender of signal to auditd
+pub const AUDIT_SIGNAL_INFO: u16 = 1010;
+/// Add syscall filtering rule
+pub const AUDIT_ADD_RULE: u16 = 1011;
+/// Delete syscall filtering rule
+pub const AUDIT_DEL_RULE: u16 = 1012;
+/// List syscall filtering rules
+pub const AUDIT_LIST_RULES: u16 = 1013;
+/// Get TTY auditing status
+pub const AUDIT_TTY_GET: u16 = 1016;
+/// Set TTY auditing status
+pub const AUDIT_TTY_SET: u16 = 1017;
+
+/// First of the userspace messages
+pub const AUDIT_FIRST_USER_MSG: u16 = 1100;
+/// Last of the userspace messages
+pub const AUDIT_LAST_USER_MSG: u16 = 1199;
+
+/// Syscall event
+pub const AUDIT_SYSCALL: u16 = 1300;
+/// Filename path information
+pub const AUDIT_PATH: u16 = 1302;
+/// IPC record
+pub const AUDIT_IPC: u16 = 1303;
+/// sys_socketcall arguments
+pub const AUDIT_SOCKETCALL: u16 = 1304;
+/// Audit system configuration change
+pub const AUDIT_CONFIG_CHANGE: u16 = 1305;
+/// sockaddr copied as syscall arg
+pub const AUDIT_SOCKADDR: u16 = 1306;
+/// Current working directory
+pub const AUDIT_CWD: u16 = 1307;
+/// execve arguments
+pub const AUDIT_EXECVE: u16 = 1309;
+/// End of multi-record event
+pub const AUDIT_EOE: u16 = 1320;
+/// Process title, the full command line
+pub const AUDIT_PROCTITLE: u16 = 1327;
+
+/// SE Linux avc denial or grant
+pub const AUDIT_AVC: u16 = 1400;
+
+/// Asynchronous audit record, not an event
+pub const AUDIT_KERNEL: u16 = 2000;
+
+/// One audit record: its type and the text the kernel formatted for it
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct AuditRecord<'a> {
+    record_type: u16,
+    body: &'a [u8],
+}
+
+impl<'a> AuditRecord<'a> {
+    /// Extracts the record from a message received on a `Protocol::Audit`
+    /// socket.
+    pub fn parse(msg: &Msg<'a>) -> io::Result<AuditRecord<'a>> {
+        match *msg.payload() {
+            Payload::Data(body) => Ok(AuditRecord {
+                record_type: msg.header().msg_type().into(),
+                body,
+            }),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "message does not carry an audit record",
+            )),
+        }
+    }
+
+    /// One of the `AUDIT_*` message type constants
+    pub fn record_type(&self) -> u16 {
+        self.record_type
+    }
+
+    /// The body exactly as received
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+
+    /// The body as text, without the trailing NULs some senders append
+    pub fn text(&self) -> io::Result<&'a str> {
+        let end = self.body.iter().rposition(|&b| b != 0).map_or(0, |n| n + 1);
+        str::from_utf8(&self.body[..end]).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socket::NlMsgHeader;
+
+    #[test]
+    fn test_parse_audit_record() {
+        let text = "audit(1364481363.243:24287): arch=c000003e syscall=2 success=no exit=-13";
+        let mut body: Vec<u8> = text.into();
+        // the kernel pads the text to the message alignment
+        body.extend_from_slice(&[0, 0, 0]);
+
+        let hdr = NlMsgHeader::user_defined(AUDIT_SYSCALL, body.len() as u32);
+        let mut bytes: Vec<u8> = hdr.bytes().into();
+        bytes.extend_from_slice(&body);
+
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        let record = AuditRecord::parse(&msg).unwrap();
+        assert_eq!(record.record_type(), AUDIT_SYSCALL);
+        assert_eq!(record.body(), &body[..]);
+        assert_eq!(record.text().unwrap(), text);
+    }
+
+    #[test]
+    fn test_parse_non_record() {
+        let hdr = NlMsgHeader::done();
+        let (msg, _) = Msg::from_bytes(hdr.bytes()).unwrap();
+        assert!(AuditRecord::parse(&msg).is_err());
+    }
+}
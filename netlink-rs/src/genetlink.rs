@@ -0,0 +1,657 @@
+use byteorder::{NativeEndian, ReadBytesExt};
+use socket::{AttrBuilder, AttrIter, Msg, NetlinkAddr, NlAttr, NlMsgHeader, Payload, Socket};
+use std::io::{self, Cursor, ErrorKind};
+use std::mem::size_of;
+use std::slice::from_raw_parts;
+use Protocol;
+
+/// Message type of the generic netlink controller
+pub const GENL_ID_CTRL: u16 = 0x10;
+
+// Flags of a `CtrlAttrOp::Flags` entry, from linux/genetlink.h
+pub const GENL_ADMIN_PERM: u32 = 0x01;
+pub const GENL_CMD_CAP_DO: u32 = 0x02;
+pub const GENL_CMD_CAP_DUMP: u32 = 0x04;
+pub const GENL_CMD_CAP_HASPOL: u32 = 0x08;
+pub const GENL_UNS_ADMIN_PERM: u32 = 0x10;
+
+/// Commands understood by the generic netlink controller
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CtrlCmd {
+    Unspec,
+    /// Family registered, also the reply to GetFamily
+    NewFamily,
+    /// Family unregistered
+    DelFamily,
+    /// Query one family by name or id, or dump all
+    GetFamily,
+    NewOps,
+    DelOps,
+    GetOps,
+    NewMcastGrp,
+    DelMcastGrp,
+    GetMcastGrp,
+    /// Query the attribute policies of a family
+    GetPolicy,
+    /// Command unknown to this crate
+    Unknown(u8),
+}
+
+impl From<CtrlCmd> for u8 {
+    fn from(c: CtrlCmd) -> u8 {
+        use self::CtrlCmd::*;
+        match c {
+            Unspec => 0,
+            NewFamily => 1,
+            DelFamily => 2,
+            GetFamily => 3,
+            NewOps => 4,
+            DelOps => 5,
+            GetOps => 6,
+            NewMcastGrp => 7,
+            DelMcastGrp => 8,
+            GetMcastGrp => 9,
+            GetPolicy => 10,
+            Unknown(i) => i,
+        }
+    }
+}
+
+impl From<u8> for CtrlCmd {
+    fn from(c: u8) -> CtrlCmd {
+        use self::CtrlCmd::*;
+        match c {
+            0 => Unspec,
+            1 => NewFamily,
+            2 => DelFamily,
+            3 => GetFamily,
+            4 => NewOps,
+            5 => DelOps,
+            6 => GetOps,
+            7 => NewMcastGrp,
+            8 => DelMcastGrp,
+            9 => GetMcastGrp,
+            10 => GetPolicy,
+            i => Unknown(i),
+        }
+    }
+}
+
+/// Top level attributes of controller messages
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CtrlAttr {
+    Unspec,
+    /// u16 message type of the family
+    FamilyId,
+    /// NUL terminated family name
+    FamilyName,
+    /// u32 family version
+    Version,
+    /// u32 size of the family specific header
+    HdrSize,
+    /// u32 highest attribute type of the family
+    MaxAttr,
+    /// Nested list of supported operations
+    Ops,
+    /// Nested list of multicast groups
+    McastGroups,
+    Policy,
+    OpPolicy,
+    Op,
+    /// Attribute unknown to this crate
+    Unknown(u16),
+}
+
+impl From<CtrlAttr> for u16 {
+    fn from(a: CtrlAttr) -> u16 {
+        use self::CtrlAttr::*;
+        match a {
+            Unspec => 0,
+            FamilyId => 1,
+            FamilyName => 2,
+            Version => 3,
+            HdrSize => 4,
+            MaxAttr => 5,
+            Ops => 6,
+            McastGroups => 7,
+            Policy => 8,
+            OpPolicy => 9,
+            Op => 10,
+            Unknown(i) => i,
+        }
+    }
+}
+
+impl From<u16> for CtrlAttr {
+    fn from(a: u16) -> CtrlAttr {
+        use self::CtrlAttr::*;
+        match a {
+            0 => Unspec,
+            1 => FamilyId,
+            2 => FamilyName,
+            3 => Version,
+            4 => HdrSize,
+            5 => MaxAttr,
+            6 => Ops,
+            7 => McastGroups,
+            8 => Policy,
+            9 => OpPolicy,
+            10 => Op,
+            i => Unknown(i),
+        }
+    }
+}
+
+/// Attributes of one entry in `CtrlAttr::McastGroups`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CtrlAttrMcastGrp {
+    Unspec,
+    /// NUL terminated group name
+    Name,
+    /// u32 group id, as used with `NETLINK_ADD_MEMBERSHIP`
+    Id,
+    /// Attribute unknown to this crate
+    Unknown(u16),
+}
+
+impl From<CtrlAttrMcastGrp> for u16 {
+    fn from(a: CtrlAttrMcastGrp) -> u16 {
+        use self::CtrlAttrMcastGrp::*;
+        match a {
+            Unspec => 0,
+            Name => 1,
+            Id => 2,
+            Unknown(i) => i,
+        }
+    }
+}
+
+impl From<u16> for CtrlAttrMcastGrp {
+    fn from(a: u16) -> CtrlAttrMcastGrp {
+        use self::CtrlAttrMcastGrp::*;
+        match a {
+            0 => Unspec,
+            1 => Name,
+            2 => Id,
+            i => Unknown(i),
+        }
+    }
+}
+
+/// Attributes of one entry in `CtrlAttr::Ops`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CtrlAttrOp {
+    Unspec,
+    /// u32 command number
+    Id,
+    /// u32 `GENL_*` flags of the command
+    Flags,
+    /// Attribute unknown to this crate
+    Unknown(u16),
+}
+
+impl From<CtrlAttrOp> for u16 {
+    fn from(a: CtrlAttrOp) -> u16 {
+        use self::CtrlAttrOp::*;
+        match a {
+            Unspec => 0,
+            Id => 1,
+            Flags => 2,
+            Unknown(i) => i,
+        }
+    }
+}
+
+impl From<u16> for CtrlAttrOp {
+    fn from(a: u16) -> CtrlAttrOp {
+        use self::CtrlAttrOp::*;
+        match a {
+            0 => Unspec,
+            1 => Id,
+            2 => Flags,
+            i => Unknown(i),
+        }
+    }
+}
+
+// HEADER FORMAT
+// __u8  cmd;
+// __u8  version;
+// __u16 reserved;
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct GenlMsgHeader {
+    cmd: u8,
+    version: u8,
+    reserved: u16,
+}
+
+impl GenlMsgHeader {
+    pub fn new<C: Into<u8>>(cmd: C, version: u8) -> GenlMsgHeader {
+        GenlMsgHeader {
+            cmd: cmd.into(),
+            version,
+            reserved: 0,
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(GenlMsgHeader, usize)> {
+        let mut cursor = Cursor::new(bytes);
+        let cmd = cursor.read_u8()?;
+        let version = cursor.read_u8()?;
+        let reserved = cursor.read_u16::<NativeEndian>()?;
+        Ok((
+            GenlMsgHeader {
+                cmd,
+                version,
+                reserved,
+            },
+            cursor.position() as usize,
+        ))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        let size = size_of::<GenlMsgHeader>();
+        unsafe {
+            let head = self as *const GenlMsgHeader as *const u8;
+            from_raw_parts(head, size)
+        }
+    }
+
+    pub fn cmd(&self) -> u8 {
+        self.cmd
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+}
+
+/// Family description as reported by the controller
+struct Family {
+    cmd: CtrlCmd,
+    id: u16,
+    name: String,
+    groups: Vec<(String, u32)>,
+    ops: Vec<(u32, u32)>,
+}
+
+impl Family {
+    fn from_bytes(bytes: &[u8]) -> io::Result<Family> {
+        let (genl, n) = GenlMsgHeader::from_bytes(bytes)?;
+        let mut id = None;
+        let mut name = String::new();
+        let mut groups = vec![];
+        let mut ops = vec![];
+
+        for attr in AttrIter::new(&bytes[n..]) {
+            let attr = attr?;
+            match CtrlAttr::from(attr.kind()) {
+                CtrlAttr::FamilyId => id = Some(attr.as_u16()?),
+                CtrlAttr::FamilyName => name = attr.as_str()?.to_owned(),
+                CtrlAttr::McastGroups => groups = parse_mcast_groups(&attr)?,
+                CtrlAttr::Ops => ops = parse_ops(&attr)?,
+                _ => {}
+            }
+        }
+
+        match id {
+            Some(id) => Ok(Family {
+                cmd: genl.cmd().into(),
+                id,
+                name,
+                groups,
+                ops,
+            }),
+            None => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "controller reply lacks a family id",
+            )),
+        }
+    }
+}
+
+/// A family registering or going away, as announced to the controller's
+/// "notify" multicast group
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FamilyNotification {
+    cmd: CtrlCmd,
+    id: u16,
+    name: String,
+}
+
+impl FamilyNotification {
+    /// Decodes the payload of a controller message, which must be a
+    /// `NewFamily` or `DelFamily` command
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<FamilyNotification> {
+        let family = Family::from_bytes(bytes)?;
+        match family.cmd {
+            CtrlCmd::NewFamily | CtrlCmd::DelFamily => Ok(FamilyNotification {
+                cmd: family.cmd,
+                id: family.id,
+                name: family.name,
+            }),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a family notification",
+            )),
+        }
+    }
+
+    /// `CtrlCmd::NewFamily` or `CtrlCmd::DelFamily`
+    pub fn cmd(&self) -> CtrlCmd {
+        self.cmd
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+fn parse_mcast_groups(attr: &NlAttr) -> io::Result<Vec<(String, u32)>> {
+    let mut groups = vec![];
+    // each group is nested again, indexed from 1
+    for group in attr.nested() {
+        let mut name = None;
+        let mut id = None;
+        for a in group?.nested() {
+            let a = a?;
+            match CtrlAttrMcastGrp::from(a.kind()) {
+                CtrlAttrMcastGrp::Name => name = Some(a.as_str()?.to_owned()),
+                CtrlAttrMcastGrp::Id => id = Some(a.as_u32()?),
+                _ => {}
+            }
+        }
+        if let (Some(name), Some(id)) = (name, id) {
+            groups.push((name, id));
+        }
+    }
+    Ok(groups)
+}
+
+fn parse_ops(attr: &NlAttr) -> io::Result<Vec<(u32, u32)>> {
+    let mut ops = vec![];
+    // nested like the multicast groups, an op without flags has none
+    for op in attr.nested() {
+        let mut id = None;
+        let mut flags = 0;
+        for a in op?.nested() {
+            let a = a?;
+            match CtrlAttrOp::from(a.kind()) {
+                CtrlAttrOp::Id => id = Some(a.as_u32()?),
+                CtrlAttrOp::Flags => flags = a.as_u32()?,
+                _ => {}
+            }
+        }
+        if let Some(id) = id {
+            ops.push((id, flags));
+        }
+    }
+    Ok(ops)
+}
+
+fn get_family(socket: &mut Socket, name: &str) -> io::Result<Family> {
+    let mut attrs = AttrBuilder::new();
+    attrs.put_str(CtrlAttr::FamilyName.into(), name);
+
+    let mut payload: Vec<u8> = GenlMsgHeader::new(CtrlCmd::GetFamily, 1).bytes().into();
+    payload.extend_from_slice(attrs.bytes());
+
+    let hdr = NlMsgHeader::user_defined(GENL_ID_CTRL, payload.len() as u32);
+    let msg = Msg::new(hdr, Payload::Data(&payload));
+
+    let (_, messages) = socket.send_and_recv(msg, &NetlinkAddr::new(0, 0))?;
+    for msg in messages {
+        match *msg.payload() {
+            Payload::Data(b) => return Family::from_bytes(b),
+            Payload::Err(errno, _) => return Err(io::Error::from_raw_os_error(-errno)),
+            _ => {}
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        "no reply from generic netlink controller",
+    ))
+}
+
+/// Looks up the message type of a generic netlink family by name
+pub fn resolve_family(socket: &mut Socket, name: &str) -> io::Result<u16> {
+    get_family(socket, name).map(|f| f.id)
+}
+
+/// Looks up a family by name, returning its id together with its multicast
+/// groups as `(name, group id)` pairs
+pub fn resolve_family_full(
+    socket: &mut Socket,
+    name: &str,
+) -> io::Result<(u16, Vec<(String, u32)>)> {
+    get_family(socket, name).map(|f| (f.id, f.groups))
+}
+
+/// Looks up a family by name, returning its id together with the commands
+/// it supports as `(cmd, GENL_* flags)` pairs
+pub fn resolve_family_ops(socket: &mut Socket, name: &str) -> io::Result<(u16, Vec<(u32, u32)>)> {
+    get_family(socket, name).map(|f| (f.id, f.ops))
+}
+
+/// A socket bound to one resolved family, framing each command with the
+/// family id and a genlmsghdr
+pub struct GenlSocket {
+    socket: Socket,
+    family: u16,
+}
+
+impl GenlSocket {
+    pub fn new(socket: Socket, family: u16) -> GenlSocket {
+        GenlSocket { socket, family }
+    }
+
+    /// Opens a `Protocol::Generic` socket for the family called `name`
+    pub fn resolve(name: &str) -> io::Result<GenlSocket> {
+        let mut socket = Socket::new(Protocol::Generic)?;
+        let family = resolve_family(&mut socket, name)?;
+        Ok(GenlSocket::new(socket, family))
+    }
+
+    pub fn family(&self) -> u16 {
+        self.family
+    }
+
+    /// The underlying socket, to receive replies
+    pub fn socket(&mut self) -> &mut Socket {
+        &mut self.socket
+    }
+
+    /// Request `cmd` with the encoded attributes `attrs`, as sent by
+    /// `send_cmd`
+    pub fn cmd_bytes(&self, cmd: u8, version: u8, attrs: &[u8]) -> io::Result<Vec<u8>> {
+        let payload = cmd_payload(cmd, version, attrs);
+        self.cmd_msg(&payload).bytes()
+    }
+
+    /// Sends `cmd` of the family to the kernel
+    pub fn send_cmd(&self, cmd: u8, version: u8, attrs: &[u8]) -> io::Result<usize> {
+        let payload = cmd_payload(cmd, version, attrs);
+        self.socket
+            .send(self.cmd_msg(&payload), &NetlinkAddr::new(0, 0))
+    }
+
+    // A request of the family carrying `payload`
+    fn cmd_msg<'a>(&self, payload: &'a [u8]) -> Msg<'a> {
+        let hdr = NlMsgHeader::user_defined(self.family, payload.len() as u32);
+        Msg::new(hdr, Payload::Data(payload))
+    }
+}
+
+// The genlmsghdr of `cmd` followed by `attrs`
+fn cmd_payload(cmd: u8, version: u8, attrs: &[u8]) -> Vec<u8> {
+    let mut payload: Vec<u8> = GenlMsgHeader::new(cmd, version).bytes().into();
+    payload.extend_from_slice(attrs);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // see Socket::try_new
+    fn generic_socket() -> Option<Socket> {
+        Socket::try_new(Protocol::Generic).unwrap()
+    }
+
+    #[test]
+    fn test_genl_header_round_trip() {
+        let hdr = GenlMsgHeader::new(CtrlCmd::GetFamily, 2);
+        let (decoded, n) = GenlMsgHeader::from_bytes(hdr.bytes()).unwrap();
+        assert_eq!(n, 4);
+        assert_eq!(decoded, hdr);
+        assert_eq!(decoded.cmd(), 3);
+        assert_eq!(decoded.version(), 2);
+    }
+
+    #[test]
+    fn test_family_decode() {
+        let mut notify = AttrBuilder::new();
+        notify
+            .put_str(CtrlAttrMcastGrp::Name.into(), "notify")
+            .put_u32(CtrlAttrMcastGrp::Id.into(), 16);
+        let mut scan = AttrBuilder::new();
+        scan.put_u32(CtrlAttrMcastGrp::Id.into(), 21)
+            .put_str(CtrlAttrMcastGrp::Name.into(), "scan");
+        let mut groups = AttrBuilder::new();
+        groups.put_nested(1, &notify).put_nested(2, &scan);
+
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_str(CtrlAttr::FamilyName.into(), "nl80211")
+            .put_u16(CtrlAttr::FamilyId.into(), 28)
+            .put_nested(CtrlAttr::McastGroups.into(), &groups);
+
+        let mut bytes: Vec<u8> = GenlMsgHeader::new(CtrlCmd::NewFamily, 2).bytes().into();
+        bytes.extend_from_slice(attrs.bytes());
+
+        let family = Family::from_bytes(&bytes).unwrap();
+        assert_eq!(family.id, 28);
+        assert_eq!(
+            family.groups,
+            vec![("notify".to_owned(), 16), ("scan".to_owned(), 21)]
+        );
+        assert!(family.ops.is_empty());
+    }
+
+    #[test]
+    fn test_family_ops_decode() {
+        let mut get = AttrBuilder::new();
+        get.put_u32(CtrlAttrOp::Id.into(), 3).put_u32(
+            CtrlAttrOp::Flags.into(),
+            GENL_CMD_CAP_DO | GENL_CMD_CAP_DUMP,
+        );
+        let mut policy = AttrBuilder::new();
+        policy.put_u32(CtrlAttrOp::Id.into(), 10);
+        let mut ops = AttrBuilder::new();
+        ops.put_nested(1, &get).put_nested(2, &policy);
+
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_u16(CtrlAttr::FamilyId.into(), GENL_ID_CTRL)
+            .put_nested(CtrlAttr::Ops.into(), &ops);
+        let mut bytes: Vec<u8> = GenlMsgHeader::new(CtrlCmd::NewFamily, 2).bytes().into();
+        bytes.extend_from_slice(attrs.bytes());
+
+        let family = Family::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            family.ops,
+            vec![(3, GENL_CMD_CAP_DO | GENL_CMD_CAP_DUMP), (10, 0)]
+        );
+    }
+
+    #[test]
+    fn test_family_notification_decode() {
+        // a module registering the "wireguard" family
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_str(CtrlAttr::FamilyName.into(), "wireguard")
+            .put_u16(CtrlAttr::FamilyId.into(), 33)
+            .put_u32(CtrlAttr::Version.into(), 1);
+        let mut bytes: Vec<u8> = GenlMsgHeader::new(CtrlCmd::NewFamily, 2).bytes().into();
+        bytes.extend_from_slice(attrs.bytes());
+
+        let event = FamilyNotification::from_bytes(&bytes).unwrap();
+        assert_eq!(event.cmd(), CtrlCmd::NewFamily);
+        assert_eq!(event.id(), 33);
+        assert_eq!(event.name(), "wireguard");
+
+        bytes[0] = CtrlCmd::GetFamily.into();
+        assert!(FamilyNotification::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_ctrl_constants() {
+        let cmd: u8 = CtrlCmd::GetFamily.into();
+        assert_eq!(cmd, 3);
+        assert_eq!(CtrlCmd::from(1), CtrlCmd::NewFamily);
+        assert_eq!(CtrlCmd::from(200), CtrlCmd::Unknown(200));
+
+        let attr: u16 = CtrlAttr::McastGroups.into();
+        assert_eq!(attr, 7);
+        assert_eq!(CtrlAttr::from(2), CtrlAttr::FamilyName);
+        assert_eq!(CtrlAttrMcastGrp::from(2), CtrlAttrMcastGrp::Id);
+        assert_eq!(CtrlAttrOp::from(1), CtrlAttrOp::Id);
+    }
+
+    #[test]
+    fn test_resolve_controller() {
+        let Some(mut socket) = generic_socket() else {
+            return;
+        };
+        let (id, groups) = resolve_family_full(&mut socket, "nlctrl").unwrap();
+        assert_eq!(id, GENL_ID_CTRL);
+        assert!(groups.iter().any(|g| g.0 == "notify"));
+        assert_eq!(resolve_family(&mut socket, "nlctrl").unwrap(), GENL_ID_CTRL);
+        let (_, ops) = resolve_family_ops(&mut socket, "nlctrl").unwrap();
+        let get_family = u32::from(u8::from(CtrlCmd::GetFamily));
+        assert!(ops.iter().any(|&(cmd, _)| cmd == get_family));
+    }
+
+    #[test]
+    fn test_genl_socket() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_str(CtrlAttr::FamilyName.into(), "nlctrl");
+        if generic_socket().is_none() {
+            return;
+        }
+        let mut genl = GenlSocket::resolve("nlctrl").unwrap();
+        assert_eq!(genl.family(), GENL_ID_CTRL);
+
+        let bytes = genl.cmd_bytes(3, 1, attrs.bytes()).unwrap();
+        // a request of the family, the genlmsghdr, then the padded name
+        let hdr = NlMsgHeader::user_defined(GENL_ID_CTRL, 16);
+        let mut expected: Vec<u8> = hdr.bytes().into();
+        expected.extend_from_slice(GenlMsgHeader::new(3, 1).bytes());
+        expected.extend_from_slice(attrs.bytes());
+        assert_eq!(attrs.bytes().len(), 12);
+        assert_eq!(bytes, expected);
+
+        assert_eq!(genl.send_cmd(3, 1, attrs.bytes()).unwrap(), 32);
+        let (_, messages) = genl.socket().recv().unwrap();
+        let reply = match *messages[0].payload() {
+            Payload::Data(b) => Family::from_bytes(b).unwrap(),
+            _ => panic!("msg is not Data enum"),
+        };
+        assert_eq!(reply.id, GENL_ID_CTRL);
+    }
+
+    #[test]
+    fn test_resolve_unknown_family() {
+        let Some(mut socket) = generic_socket() else {
+            return;
+        };
+        let err = resolve_family(&mut socket, "no-such-family").unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(::libc::ENOENT));
+    }
+}
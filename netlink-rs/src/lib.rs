@@ -1,13 +1,24 @@
-#![cfg_attr(feature="clippy", feature(plugin))]
-#![cfg_attr(feature="clippy", plugin(clippy))]
-
 extern crate libc;
 extern crate byteorder;
 
 pub mod socket;
 
-use std::convert::Into;
+#[cfg(feature = "genetlink")]
+pub mod genetlink;
+
+#[cfg(feature = "audit")]
+pub mod audit;
+
+#[cfg(feature = "rtnetlink")]
+pub mod rtnetlink;
+
+#[cfg(feature = "netfilter")]
+pub mod netfilter;
+
+#[cfg(feature = "taskstats")]
+pub mod taskstats;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Protocol {
     Route,           /* 0    Routing/device hook              */
     Unused,          /* 1    Unused number                */
@@ -30,10 +41,10 @@ pub enum Protocol {
     Ecryptfs,        // 19
 }
 
-impl Into<i32> for Protocol {
-    fn into(self) -> i32 {
+impl From<Protocol> for i32 {
+    fn from(protocol: Protocol) -> i32 {
         use Protocol::*;
-        match self {
+        match protocol {
             Route => 0,
             Unused => 1,
             Usersock => 2,
@@ -56,3 +67,18 @@ impl Into<i32> for Protocol {
         }
     }
 }
+
+impl Protocol {
+    /// The variant for protocol number `n`, if it has one
+    pub fn from_i32(n: i32) -> Option<Protocol> {
+        use Protocol::*;
+        [
+            Route, Unused, Usersock, Firewall, INETDiag, Nflog, Xfrm, SELinux, Iscsi, Audit,
+            FibLookup, Connector, Netfilter, Ip6FW, Dnrtmsg, KobjectUevent, Generic,
+            SCSITransport, Ecryptfs,
+        ]
+            .iter()
+            .cloned()
+            .find(|&p| i32::from(p) == n)
+    }
+}
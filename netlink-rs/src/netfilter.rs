@@ -0,0 +1,169 @@
+use byteorder::{NativeEndian, ReadBytesExt};
+use socket::{AttrBuilder, Msg, NlMsgHeader, Payload};
+use std::io::{self, Cursor};
+use std::mem::size_of;
+use std::slice::from_raw_parts;
+
+// Subsystems, from linux/netfilter/nfnetlink.h
+pub const NFNL_SUBSYS_NONE: u8 = 0;
+pub const NFNL_SUBSYS_CTNETLINK: u8 = 1;
+pub const NFNL_SUBSYS_CTNETLINK_EXP: u8 = 2;
+pub const NFNL_SUBSYS_QUEUE: u8 = 3;
+pub const NFNL_SUBSYS_ULOG: u8 = 4;
+pub const NFNL_SUBSYS_OSF: u8 = 5;
+pub const NFNL_SUBSYS_IPSET: u8 = 6;
+pub const NFNL_SUBSYS_ACCT: u8 = 7;
+pub const NFNL_SUBSYS_CTNETLINK_TIMEOUT: u8 = 8;
+pub const NFNL_SUBSYS_CTHELPER: u8 = 9;
+pub const NFNL_SUBSYS_NFTABLES: u8 = 10;
+pub const NFNL_SUBSYS_NFT_COMPAT: u8 = 11;
+
+/// The only nfgenmsg version
+pub const NFNETLINK_V0: u8 = 0;
+
+// #define NFNL_SUBSYS_ID(x)  ((x & 0xff00) >> 8)
+/// Subsystem addressed by a netfilter message type
+pub fn subsys_id(msg_type: u16) -> u8 {
+    (msg_type >> 8) as u8
+}
+
+// #define NFNL_MSG_TYPE(x)   (x & 0x00ff)
+/// Subsystem specific message of a netfilter message type
+pub fn msg_type(msg_type: u16) -> u8 {
+    msg_type as u8
+}
+
+/// Netlink message type for message `msg` of subsystem `subsys`
+pub fn nf_msg_type(subsys: u8, msg: u8) -> u16 {
+    (u16::from(subsys) << 8) | u16::from(msg)
+}
+
+// HEADER FORMAT
+// __u8  nfgen_family;   /* AF_xxx */
+// __u8  version;        /* nfnetlink version */
+// __be16    res_id;     /* resource id */
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct NfGenMsg {
+    family: u8,
+    version: u8,
+    // network byte order
+    res_id: u16,
+}
+
+impl NfGenMsg {
+    pub fn new(family: u8, res_id: u16) -> NfGenMsg {
+        NfGenMsg {
+            family,
+            version: NFNETLINK_V0,
+            res_id: res_id.to_be(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(NfGenMsg, usize)> {
+        let mut cursor = Cursor::new(bytes);
+        let family = cursor.read_u8()?;
+        let version = cursor.read_u8()?;
+        let res_id = cursor.read_u16::<NativeEndian>()?;
+        Ok((
+            NfGenMsg {
+                family,
+                version,
+                res_id,
+            },
+            cursor.position() as usize,
+        ))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        let size = size_of::<NfGenMsg>();
+        unsafe {
+            let head = self as *const NfGenMsg as *const u8;
+            from_raw_parts(head, size)
+        }
+    }
+
+    /// AF_* family the message is about
+    pub fn family(&self) -> u8 {
+        self.family
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Resource id in host byte order, e.g. the queue number for nfqueue
+    pub fn res_id(&self) -> u16 {
+        u16::from_be(self.res_id)
+    }
+}
+
+/// A netfilter request: the netlink header followed by an nfgenmsg and
+/// attributes. Owns the payload the `Msg` returned by `msg` borrows.
+#[derive(Clone, Debug)]
+pub struct NfRequest {
+    header: NlMsgHeader,
+    payload: Vec<u8>,
+}
+
+impl NfRequest {
+    pub fn new(subsys: u8, msg: u8, genmsg: NfGenMsg, attrs: &AttrBuilder) -> NfRequest {
+        let mut payload: Vec<u8> = genmsg.bytes().into();
+        payload.extend_from_slice(attrs.bytes());
+        let header = NlMsgHeader::user_defined(nf_msg_type(subsys, msg), payload.len() as u32);
+        NfRequest { header, payload }
+    }
+
+    /// The netlink header, to set flags such as `dump` or `ack`
+    pub fn header(&mut self) -> &mut NlMsgHeader {
+        &mut self.header
+    }
+
+    pub fn msg(&self) -> Msg<'_> {
+        Msg::new(self.header, Payload::Data(&self.payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socket::{AttrIter, MsgType};
+
+    #[test]
+    fn test_nfgenmsg_round_trip() {
+        let genmsg = NfGenMsg::new(2, 0x0102);
+        // res_id is big endian on the wire
+        assert_eq!(genmsg.bytes(), &[2, 0, 1, 2]);
+
+        let (decoded, n) = NfGenMsg::from_bytes(genmsg.bytes()).unwrap();
+        assert_eq!(n, 4);
+        assert_eq!(decoded, genmsg);
+        assert_eq!(decoded.family(), 2);
+        assert_eq!(decoded.version(), NFNETLINK_V0);
+        assert_eq!(decoded.res_id(), 0x0102);
+    }
+
+    #[test]
+    fn test_request_encoding() {
+        // IPCTNL_MSG_CT_GET on the conntrack subsystem
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7);
+        let mut request = NfRequest::new(NFNL_SUBSYS_CTNETLINK, 1, NfGenMsg::new(2, 0), &attrs);
+        request.header().dump();
+
+        let bytes = request.msg().bytes().unwrap();
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(msg.header().msg_type(), MsgType::UserDefined(0x0101));
+        assert_eq!(subsys_id(0x0101), NFNL_SUBSYS_CTNETLINK);
+        assert_eq!(msg_type(0x0101), 1);
+
+        let body = match *msg.payload() {
+            Payload::Data(b) => b,
+            _ => panic!("msg is not Data enum"),
+        };
+        let (genmsg, n) = NfGenMsg::from_bytes(body).unwrap();
+        assert_eq!(genmsg.family(), 2);
+        let attr = AttrIter::new(&body[n..]).next().unwrap().unwrap();
+        assert_eq!(attr.as_u32().unwrap(), 7);
+    }
+}
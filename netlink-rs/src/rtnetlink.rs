@@ -0,0 +1,1185 @@
+use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use libc::ENODEV;
+use socket::{AttrBuilder, Msg, MsgIter, MsgType, NetlinkAddr, NlMsgHeader, Payload, Socket};
+use std::collections::HashMap;
+use std::io::{self, Cursor, ErrorKind};
+use std::str;
+
+// #define RTA_ALIGNTO     4U
+const RTA_ALIGNTO: usize = 4;
+
+// #define RTA_ALIGN(len) ( ((len)+RTA_ALIGNTO-1) & ~(RTA_ALIGNTO-1) )
+#[inline]
+fn rta_align(len: usize) -> usize {
+    (len + (RTA_ALIGNTO - 1)) & !(RTA_ALIGNTO - 1)
+}
+
+// RTA_LENGTH(0)
+// #define RTA_LENGTH(len) (RTA_ALIGN(sizeof(struct rtattr)) + (len))
+#[inline]
+fn rta_header_length() -> usize {
+    rta_align(4)
+}
+
+// Link message types, from linux/rtnetlink.h
+pub const RTM_NEWLINK: u16 = 16;
+pub const RTM_DELLINK: u16 = 17;
+pub const RTM_GETLINK: u16 = 18;
+pub const RTM_SETLINK: u16 = 19;
+
+// Route message types, from linux/rtnetlink.h
+pub const RTM_NEWROUTE: u16 = 24;
+pub const RTM_DELROUTE: u16 = 25;
+pub const RTM_GETROUTE: u16 = 26;
+
+// Address message types, from linux/rtnetlink.h
+pub const RTM_NEWADDR: u16 = 20;
+pub const RTM_DELADDR: u16 = 21;
+pub const RTM_GETADDR: u16 = 22;
+
+// Link attributes, from linux/if_link.h
+pub const IFLA_UNSPEC: u16 = 0;
+pub const IFLA_ADDRESS: u16 = 1;
+pub const IFLA_BROADCAST: u16 = 2;
+pub const IFLA_IFNAME: u16 = 3;
+pub const IFLA_MTU: u16 = 4;
+pub const IFLA_LINK: u16 = 5;
+pub const IFLA_QDISC: u16 = 6;
+pub const IFLA_STATS: u16 = 7;
+pub const IFLA_MASTER: u16 = 10;
+pub const IFLA_TXQLEN: u16 = 13;
+pub const IFLA_OPERSTATE: u16 = 16;
+pub const IFLA_LINKMODE: u16 = 17;
+pub const IFLA_LINKINFO: u16 = 18;
+pub const IFLA_STATS64: u16 = 23;
+pub const IFLA_GROUP: u16 = 27;
+pub const IFLA_EXT_MASK: u16 = 29;
+pub const IFLA_PROMISCUITY: u16 = 30;
+
+// Attributes nested in IFLA_LINKINFO
+pub const IFLA_INFO_UNSPEC: u16 = 0;
+pub const IFLA_INFO_KIND: u16 = 1;
+pub const IFLA_INFO_DATA: u16 = 2;
+pub const IFLA_INFO_XSTATS: u16 = 3;
+pub const IFLA_INFO_SLAVE_KIND: u16 = 4;
+pub const IFLA_INFO_SLAVE_DATA: u16 = 5;
+
+// IFLA_EXT_MASK filter flags, from linux/rtnetlink.h
+/// Include `IFLA_VFINFO_LIST`, the SR-IOV virtual functions
+pub const RTEXT_FILTER_VF: u32 = 1 << 0;
+/// Include the bridge VLANs of bridge ports
+pub const RTEXT_FILTER_BRVLAN: u32 = 1 << 1;
+/// Bridge VLANs as ranges rather than one entry per VLAN
+pub const RTEXT_FILTER_BRVLAN_COMPRESSED: u32 = 1 << 2;
+/// Leave out the statistics attributes
+pub const RTEXT_FILTER_SKIP_STATS: u32 = 1 << 3;
+/// Include the Media Redundancy Protocol state of bridge ports
+pub const RTEXT_FILTER_MRP: u32 = 1 << 4;
+/// Include the Connectivity Fault Management configuration of bridges
+pub const RTEXT_FILTER_CFM_CONFIG: u32 = 1 << 5;
+/// Include the Connectivity Fault Management status of bridges
+pub const RTEXT_FILTER_CFM_STATUS: u32 = 1 << 6;
+/// Include the Multiple Spanning Tree state of bridge ports
+pub const RTEXT_FILTER_MST: u32 = 1 << 7;
+
+// Address attributes, from linux/if_addr.h
+pub const IFA_UNSPEC: u16 = 0;
+pub const IFA_ADDRESS: u16 = 1;
+pub const IFA_LOCAL: u16 = 2;
+pub const IFA_LABEL: u16 = 3;
+pub const IFA_BROADCAST: u16 = 4;
+pub const IFA_ANYCAST: u16 = 5;
+pub const IFA_CACHEINFO: u16 = 6;
+pub const IFA_MULTICAST: u16 = 7;
+pub const IFA_FLAGS: u16 = 8;
+
+// Route attributes, from linux/rtnetlink.h
+pub const RTA_UNSPEC: u16 = 0;
+pub const RTA_DST: u16 = 1;
+pub const RTA_SRC: u16 = 2;
+pub const RTA_IIF: u16 = 3;
+pub const RTA_OIF: u16 = 4;
+pub const RTA_GATEWAY: u16 = 5;
+pub const RTA_PRIORITY: u16 = 6;
+pub const RTA_PREFSRC: u16 = 7;
+pub const RTA_METRICS: u16 = 8;
+pub const RTA_MULTIPATH: u16 = 9;
+pub const RTA_TABLE: u16 = 15;
+
+// sizeof(struct rtnl_link_stats64) up to rx_nohandler
+const RTNL_LINK_STATS64_LEN: usize = 24 * 8;
+// sizeof(struct ifa_cacheinfo)
+const IFA_CACHEINFO_LEN: usize = 16;
+// sizeof(struct rtnexthop)
+const RTNEXTHOP_LEN: usize = 8;
+
+/// How an `RTM_NEWROUTE` request treats routes to the same destination
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RouteAddMode {
+    /// Add the route, failing with EEXIST only if an identical one exists
+    Create,
+    /// Fail with EEXIST if a route to the destination exists
+    CreateExcl,
+    /// Add another next hop alongside existing routes (IPv6 multipath)
+    Append,
+    /// Replace the existing route, or create it
+    Replace,
+}
+
+impl RouteAddMode {
+    /// Sets the mode's flags on `hdr`
+    pub fn apply(self, hdr: &mut NlMsgHeader) -> &mut NlMsgHeader {
+        match self {
+            RouteAddMode::Create => hdr.create(),
+            RouteAddMode::CreateExcl => hdr.create().excl(),
+            RouteAddMode::Append => hdr.create().append(),
+            RouteAddMode::Replace => hdr.create().replace(),
+        }
+    }
+}
+
+impl From<RouteAddMode> for u16 {
+    fn from(mode: RouteAddMode) -> u16 {
+        let mut hdr = NlMsgHeader::user_defined(0, 0);
+        hdr.flags(0);
+        mode.apply(&mut hdr).msg_flags()
+    }
+}
+
+// HEADER FORMAT
+// unsigned char   ifi_family;
+// unsigned char   __ifi_pad;
+// unsigned short  ifi_type;    /* ARPHRD_* */
+// int             ifi_index;   /* Link index */
+// unsigned        ifi_flags;   /* IFF_* flags */
+// unsigned        ifi_change;  /* IFF_* change mask */
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct IfInfoMsg {
+    family: u8,
+    pad: u8,
+    link_type: u16,
+    index: i32,
+    flags: u32,
+    change: u32,
+}
+
+impl IfInfoMsg {
+    /// Header for a request about the link `index`, 0 meaning any link
+    pub fn new(family: u8, index: i32) -> IfInfoMsg {
+        IfInfoMsg {
+            family,
+            pad: 0,
+            link_type: 0,
+            index,
+            flags: 0,
+            change: 0,
+        }
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        // writing into a Vec cannot fail
+        bytes.write_u8(self.family).unwrap();
+        bytes.write_u8(self.pad).unwrap();
+        bytes.write_u16::<NativeEndian>(self.link_type).unwrap();
+        bytes.write_i32::<NativeEndian>(self.index).unwrap();
+        bytes.write_u32::<NativeEndian>(self.flags).unwrap();
+        bytes.write_u32::<NativeEndian>(self.change).unwrap();
+        bytes
+    }
+
+    /// Decodes the header at the start of a link message payload, returning
+    /// it with the attribute region that follows.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(IfInfoMsg, &[u8])> {
+        let mut cursor = Cursor::new(bytes);
+        let family = cursor.read_u8()?;
+        let pad = cursor.read_u8()?;
+        let link_type = cursor.read_u16::<NativeEndian>()?;
+        let index = cursor.read_i32::<NativeEndian>()?;
+        let flags = cursor.read_u32::<NativeEndian>()?;
+        let change = cursor.read_u32::<NativeEndian>()?;
+        let n = cursor.position() as usize;
+        Ok((
+            IfInfoMsg {
+                family,
+                pad,
+                link_type,
+                index,
+                flags,
+                change,
+            },
+            &bytes[n..],
+        ))
+    }
+
+    pub fn family(&self) -> u8 {
+        self.family
+    }
+
+    /// ARPHRD_* link type
+    pub fn link_type(&self) -> u16 {
+        self.link_type
+    }
+
+    pub fn index(&self) -> i32 {
+        self.index
+    }
+
+    /// IFF_* flags
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+}
+
+/// Value of `IFLA_STATS64`, the 64 bit interface counters
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RtnlLinkStats64 {
+    rx_packets: u64,
+    tx_packets: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_errors: u64,
+    tx_errors: u64,
+    rx_dropped: u64,
+    tx_dropped: u64,
+    multicast: u64,
+    collisions: u64,
+    rx_length_errors: u64,
+    rx_over_errors: u64,
+    rx_crc_errors: u64,
+    rx_frame_errors: u64,
+    rx_fifo_errors: u64,
+    rx_missed_errors: u64,
+    tx_aborted_errors: u64,
+    tx_carrier_errors: u64,
+    tx_fifo_errors: u64,
+    tx_heartbeat_errors: u64,
+    tx_window_errors: u64,
+    rx_compressed: u64,
+    tx_compressed: u64,
+    rx_nohandler: u64,
+}
+
+impl RtnlLinkStats64 {
+    /// Decodes an `IFLA_STATS64` value. Newer kernels append counters,
+    /// which are ignored.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<RtnlLinkStats64> {
+        if bytes.len() < RTNL_LINK_STATS64_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "IFLA_STATS64 value too short",
+            ));
+        }
+        let mut cursor = Cursor::new(bytes);
+        Ok(RtnlLinkStats64 {
+            rx_packets: cursor.read_u64::<NativeEndian>()?,
+            tx_packets: cursor.read_u64::<NativeEndian>()?,
+            rx_bytes: cursor.read_u64::<NativeEndian>()?,
+            tx_bytes: cursor.read_u64::<NativeEndian>()?,
+            rx_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_dropped: cursor.read_u64::<NativeEndian>()?,
+            tx_dropped: cursor.read_u64::<NativeEndian>()?,
+            multicast: cursor.read_u64::<NativeEndian>()?,
+            collisions: cursor.read_u64::<NativeEndian>()?,
+            rx_length_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_over_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_crc_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_frame_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_fifo_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_missed_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_aborted_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_carrier_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_fifo_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_heartbeat_errors: cursor.read_u64::<NativeEndian>()?,
+            tx_window_errors: cursor.read_u64::<NativeEndian>()?,
+            rx_compressed: cursor.read_u64::<NativeEndian>()?,
+            tx_compressed: cursor.read_u64::<NativeEndian>()?,
+            rx_nohandler: cursor.read_u64::<NativeEndian>()?,
+        })
+    }
+
+    /// total packets received
+    pub fn rx_packets(&self) -> u64 {
+        self.rx_packets
+    }
+
+    /// total packets transmitted
+    pub fn tx_packets(&self) -> u64 {
+        self.tx_packets
+    }
+
+    /// total bytes received
+    pub fn rx_bytes(&self) -> u64 {
+        self.rx_bytes
+    }
+
+    /// total bytes transmitted
+    pub fn tx_bytes(&self) -> u64 {
+        self.tx_bytes
+    }
+
+    /// bad packets received
+    pub fn rx_errors(&self) -> u64 {
+        self.rx_errors
+    }
+
+    /// packet transmit problems
+    pub fn tx_errors(&self) -> u64 {
+        self.tx_errors
+    }
+
+    /// no space in linux buffers
+    pub fn rx_dropped(&self) -> u64 {
+        self.rx_dropped
+    }
+
+    /// no space available in linux
+    pub fn tx_dropped(&self) -> u64 {
+        self.tx_dropped
+    }
+
+    /// multicast packets received
+    pub fn multicast(&self) -> u64 {
+        self.multicast
+    }
+
+    pub fn collisions(&self) -> u64 {
+        self.collisions
+    }
+
+    pub fn rx_length_errors(&self) -> u64 {
+        self.rx_length_errors
+    }
+
+    /// receiver ring buff overflow
+    pub fn rx_over_errors(&self) -> u64 {
+        self.rx_over_errors
+    }
+
+    /// received packets with crc error
+    pub fn rx_crc_errors(&self) -> u64 {
+        self.rx_crc_errors
+    }
+
+    /// received frame alignment error
+    pub fn rx_frame_errors(&self) -> u64 {
+        self.rx_frame_errors
+    }
+
+    /// recv'r fifo overrun
+    pub fn rx_fifo_errors(&self) -> u64 {
+        self.rx_fifo_errors
+    }
+
+    /// receiver missed packet
+    pub fn rx_missed_errors(&self) -> u64 {
+        self.rx_missed_errors
+    }
+
+    pub fn tx_aborted_errors(&self) -> u64 {
+        self.tx_aborted_errors
+    }
+
+    pub fn tx_carrier_errors(&self) -> u64 {
+        self.tx_carrier_errors
+    }
+
+    pub fn tx_fifo_errors(&self) -> u64 {
+        self.tx_fifo_errors
+    }
+
+    pub fn tx_heartbeat_errors(&self) -> u64 {
+        self.tx_heartbeat_errors
+    }
+
+    pub fn tx_window_errors(&self) -> u64 {
+        self.tx_window_errors
+    }
+
+    pub fn rx_compressed(&self) -> u64 {
+        self.rx_compressed
+    }
+
+    pub fn tx_compressed(&self) -> u64 {
+        self.tx_compressed
+    }
+
+    /// dropped, no handler found
+    pub fn rx_nohandler(&self) -> u64 {
+        self.rx_nohandler
+    }
+}
+
+// ADDRESS CACHE INFO FORMAT
+// __u32   ifa_prefered;
+// __u32   ifa_valid;
+// __u32   cstamp; /* created timestamp, hundredths of seconds */
+// __u32   tstamp; /* updated timestamp, hundredths of seconds */
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct IfaCacheInfo {
+    prefered: u32,
+    valid: u32,
+    cstamp: u32,
+    tstamp: u32,
+}
+
+impl IfaCacheInfo {
+    /// Decodes an `IFA_CACHEINFO` value
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<IfaCacheInfo> {
+        if bytes.len() != IFA_CACHEINFO_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "IFA_CACHEINFO value has unexpected size",
+            ));
+        }
+        let mut cursor = Cursor::new(bytes);
+        Ok(IfaCacheInfo {
+            prefered: cursor.read_u32::<NativeEndian>()?,
+            valid: cursor.read_u32::<NativeEndian>()?,
+            cstamp: cursor.read_u32::<NativeEndian>()?,
+            tstamp: cursor.read_u32::<NativeEndian>()?,
+        })
+    }
+
+    /// Preferred lifetime in seconds, `u32::MAX` for forever
+    pub fn prefered(&self) -> u32 {
+        self.prefered
+    }
+
+    /// Valid lifetime in seconds, `u32::MAX` for forever
+    pub fn valid(&self) -> u32 {
+        self.valid
+    }
+
+    /// Creation time in hundredths of seconds since boot
+    pub fn cstamp(&self) -> u32 {
+        self.cstamp
+    }
+
+    /// Last update time in hundredths of seconds since boot
+    pub fn tstamp(&self) -> u32 {
+        self.tstamp
+    }
+}
+
+// ATTRIBUTE FORMAT
+// unsigned short  rta_len;
+// unsigned short  rta_type;
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct RtAttr<'a> {
+    ty: u16,
+    value: &'a [u8],
+}
+
+impl<'a> RtAttr<'a> {
+    pub fn ty(&self) -> u16 {
+        self.ty
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    pub fn as_u32(&self) -> io::Result<u32> {
+        if self.value.len() != 4 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "attribute value has unexpected size",
+            ));
+        }
+        Cursor::new(self.value).read_u32::<NativeEndian>()
+    }
+
+    /// Value as a string, without the trailing NUL
+    pub fn as_str(&self) -> io::Result<&'a str> {
+        let value = match self.value.iter().position(|&b| b == 0) {
+            Some(n) => &self.value[..n],
+            None => self.value,
+        };
+        str::from_utf8(value).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// Iterator over the `rtattr` region following an rtnetlink family header
+#[derive(Clone, Debug)]
+pub struct RtAttrIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> RtAttrIter<'a> {
+    pub fn new(bytes: &'a [u8]) -> RtAttrIter<'a> {
+        RtAttrIter { bytes }
+    }
+}
+
+impl<'a> Iterator for RtAttrIter<'a> {
+    type Item = io::Result<RtAttr<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<RtAttr<'a>>> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(self.bytes);
+        let header = cursor.read_u16::<NativeEndian>().and_then(|len| {
+            cursor
+                .read_u16::<NativeEndian>()
+                .map(|ty| (len as usize, ty))
+        });
+
+        match header {
+            Ok((len, ty)) if len >= rta_header_length() && len <= self.bytes.len() => {
+                let attr = RtAttr {
+                    ty,
+                    value: &self.bytes[rta_header_length()..len],
+                };
+                let next = rta_align(len).min(self.bytes.len());
+                self.bytes = &self.bytes[next..];
+                Some(Ok(attr))
+            }
+            _ => {
+                // stop after reporting a malformed attribute
+                self.bytes = &[];
+                Some(Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "invalid rtattr length",
+                )))
+            }
+        }
+    }
+}
+
+/// The link type an `IFLA_LINKINFO` attribute describes
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkInfoKind {
+    kind: String,
+    data: Vec<u8>,
+}
+
+impl LinkInfoKind {
+    /// Driver kind, such as "vlan", "bridge" or "vxlan"
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    /// The kind specific attributes of `IFLA_INFO_DATA`, empty if absent;
+    /// walk them with `RtAttrIter`
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// Decodes the value of an `IFLA_LINKINFO` attribute
+pub fn parse_linkinfo(attr_bytes: &[u8]) -> io::Result<LinkInfoKind> {
+    let mut kind = None;
+    let mut data = vec![];
+    for attr in RtAttrIter::new(attr_bytes) {
+        let attr = attr?;
+        match attr.ty() {
+            IFLA_INFO_KIND => kind = Some(attr.as_str()?.to_owned()),
+            IFLA_INFO_DATA => data = attr.value().to_vec(),
+            _ => {}
+        }
+    }
+
+    let kind = kind.ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidData,
+            "IFLA_LINKINFO without IFLA_INFO_KIND",
+        )
+    })?;
+    Ok(LinkInfoKind { kind, data })
+}
+
+// NEXTHOP FORMAT
+// unsigned short  rtnh_len;     /* Length of struct + attributes */
+// unsigned char   rtnh_flags;   /* RTNH_F_* flags */
+// unsigned char   rtnh_hops;    /* Nexthop priority */
+// int             rtnh_ifindex; /* Interface for this hop */
+/// One next hop of a multipath route, see `parse_multipath`
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NextHop {
+    flags: u8,
+    hops: u8,
+    ifindex: i32,
+    gateway: Option<Vec<u8>>,
+    oif: Option<u32>,
+}
+
+impl NextHop {
+    /// RTNH_F_* flags
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Weight of the hop minus one
+    pub fn hops(&self) -> u8 {
+        self.hops
+    }
+
+    pub fn ifindex(&self) -> i32 {
+        self.ifindex
+    }
+
+    /// `RTA_GATEWAY`, 4 or 16 address bytes depending on the family
+    pub fn gateway(&self) -> Option<&[u8]> {
+        self.gateway.as_ref().map(|g| &g[..])
+    }
+
+    /// `RTA_OIF`, if the hop carries one besides `ifindex`
+    pub fn oif(&self) -> Option<u32> {
+        self.oif
+    }
+}
+
+/// Decodes the value of an `RTA_MULTIPATH` attribute: `rtnexthop` structs,
+/// each followed by its own attributes within its `rtnh_len`
+pub fn parse_multipath(bytes: &[u8]) -> io::Result<Vec<NextHop>> {
+    let mut hops = vec![];
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let mut cursor = Cursor::new(rest);
+        let len = cursor.read_u16::<NativeEndian>()? as usize;
+        if len < RTNEXTHOP_LEN || len > rest.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid rtnexthop length",
+            ));
+        }
+        let mut hop = NextHop {
+            flags: cursor.read_u8()?,
+            hops: cursor.read_u8()?,
+            ifindex: cursor.read_i32::<NativeEndian>()?,
+            gateway: None,
+            oif: None,
+        };
+
+        for attr in RtAttrIter::new(&rest[RTNEXTHOP_LEN..len]) {
+            let attr = attr?;
+            match attr.ty() {
+                RTA_GATEWAY => hop.gateway = Some(attr.value().to_vec()),
+                RTA_OIF => hop.oif = Some(attr.as_u32()?),
+                _ => {}
+            }
+        }
+        hops.push(hop);
+        // #define RTNH_ALIGN(len) (((len)+RTNH_ALIGNTO-1) & ~(RTNH_ALIGNTO-1))
+        rest = &rest[rta_align(len).min(rest.len())..];
+    }
+    Ok(hops)
+}
+
+/// A network interface as reported in an `RTM_NEWLINK` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkInfo {
+    index: u32,
+    name: String,
+    link_type: u16,
+    flags: u32,
+    mtu: Option<u32>,
+    address: Option<Vec<u8>>,
+}
+
+impl LinkInfo {
+    /// Decodes the payload of an `RTM_NEWLINK` message
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<LinkInfo> {
+        let (info, attrs) = IfInfoMsg::from_bytes(bytes)?;
+        let mut name = None;
+        let mut mtu = None;
+        let mut address = None;
+        for attr in RtAttrIter::new(attrs) {
+            let attr = attr?;
+            match attr.ty() {
+                IFLA_IFNAME => name = Some(attr.as_str()?.to_owned()),
+                IFLA_MTU => mtu = Some(attr.as_u32()?),
+                IFLA_ADDRESS => address = Some(attr.value().to_vec()),
+                _ => {}
+            }
+        }
+
+        let name = name.ok_or_else(|| {
+            io::Error::new(ErrorKind::InvalidData, "link message without IFLA_IFNAME")
+        })?;
+        Ok(LinkInfo {
+            index: info.index() as u32,
+            name,
+            link_type: info.link_type(),
+            flags: info.flags(),
+            mtu,
+            address,
+        })
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// ARPHRD_* link type
+    pub fn link_type(&self) -> u16 {
+        self.link_type
+    }
+
+    /// IFF_* flags
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn mtu(&self) -> Option<u32> {
+        self.mtu
+    }
+
+    /// Hardware address, absent for links without one
+    pub fn address(&self) -> Option<&[u8]> {
+        self.address.as_ref().map(|a| &a[..])
+    }
+}
+
+// HEADER FORMAT
+// __u8    ifa_family;
+// __u8    ifa_prefixlen;  /* The prefix length */
+// __u8    ifa_flags;      /* Flags */
+// __u8    ifa_scope;      /* Address scope */
+// __u32   ifa_index;      /* Link index */
+/// An interface address as reported in an `RTM_NEWADDR` message
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AddrInfo {
+    family: u8,
+    prefix_len: u8,
+    scope: u8,
+    index: u32,
+    local: Option<Vec<u8>>,
+    address: Option<Vec<u8>>,
+    label: Option<String>,
+}
+
+impl AddrInfo {
+    /// Decodes the payload of an `RTM_NEWADDR` message
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<AddrInfo> {
+        let mut cursor = Cursor::new(bytes);
+        let family = cursor.read_u8()?;
+        let prefix_len = cursor.read_u8()?;
+        // the 8 bit flags are superseded by the IFA_FLAGS attribute
+        let _flags = cursor.read_u8()?;
+        let scope = cursor.read_u8()?;
+        let index = cursor.read_u32::<NativeEndian>()?;
+        let mut addr = AddrInfo {
+            family,
+            prefix_len,
+            scope,
+            index,
+            local: None,
+            address: None,
+            label: None,
+        };
+
+        let n = cursor.position() as usize;
+        for attr in RtAttrIter::new(&bytes[n..]) {
+            let attr = attr?;
+            match attr.ty() {
+                IFA_LOCAL => addr.local = Some(attr.value().to_vec()),
+                IFA_ADDRESS => addr.address = Some(attr.value().to_vec()),
+                IFA_LABEL => addr.label = Some(attr.as_str()?.to_owned()),
+                _ => {}
+            }
+        }
+        Ok(addr)
+    }
+
+    pub fn family(&self) -> u8 {
+        self.family
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// RT_SCOPE_* scope
+    pub fn scope(&self) -> u8 {
+        self.scope
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// The address of the interface itself: `IFA_LOCAL`, or `IFA_ADDRESS`
+    /// where only that is sent, as for IPv6
+    pub fn local(&self) -> Option<&[u8]> {
+        self.local
+            .as_ref()
+            .or(self.address.as_ref())
+            .map(|a| &a[..])
+    }
+
+    /// The other end of a point-to-point link: `IFA_ADDRESS` when it
+    /// differs from `IFA_LOCAL`. On other links the two are the same.
+    pub fn peer(&self) -> Option<&[u8]> {
+        match (&self.local, &self.address) {
+            (Some(local), Some(address)) if local != address => Some(&address[..]),
+            _ => None,
+        }
+    }
+
+    /// `IFA_LABEL`, the interface name or an alias such as "eth0:1"
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_ref().map(|l| &l[..])
+    }
+}
+
+/// Dumps the network interfaces of the socket's network namespace
+pub fn list_links(socket: &mut Socket) -> io::Result<Vec<LinkInfo>> {
+    let payload = IfInfoMsg::new(0, 0).bytes();
+    let mut hdr = NlMsgHeader::user_defined(RTM_GETLINK, payload.len() as u32);
+    hdr.dump();
+    dump_links(socket, Msg::new(hdr, Payload::Data(&payload)))
+}
+
+/// Payload of an RTM_GETLINK dump request asking for the extra information
+/// in `ext_mask`, a combination of the `RTEXT_FILTER_*` flags. Send it
+/// with `Msg::dump_request`.
+pub fn link_dump_payload(ext_mask: u32) -> Vec<u8> {
+    let mut payload = IfInfoMsg::new(0, 0).bytes();
+    let mut attrs = AttrBuilder::new();
+    attrs.put_u32(IFLA_EXT_MASK, ext_mask);
+    payload.extend_from_slice(attrs.bytes());
+    payload
+}
+
+/// Like `list_links`, with the `RTEXT_FILTER_*` flags in `ext_mask`, e.g.
+/// `RTEXT_FILTER_VF` for the virtual functions of SR-IOV devices
+pub fn list_links_with_mask(socket: &mut Socket, ext_mask: u32) -> io::Result<Vec<LinkInfo>> {
+    let payload = link_dump_payload(ext_mask);
+    dump_links(
+        socket,
+        Msg::dump_request(MsgType::UserDefined(RTM_GETLINK), &payload),
+    )
+}
+
+fn dump_links(socket: &mut Socket, request: Msg) -> io::Result<Vec<LinkInfo>> {
+    socket.send(request, &NetlinkAddr::new(0, 0))?;
+
+    let dump = socket.recv_dump_bytes()?;
+    let mut links = vec![];
+    for msg in MsgIter::new(&dump) {
+        let msg = msg?;
+        if let (MsgType::UserDefined(RTM_NEWLINK), &Payload::Data(b)) =
+            (msg.header().msg_type(), msg.payload())
+        {
+            links.push(LinkInfo::from_bytes(b)?);
+        }
+    }
+    Ok(links)
+}
+
+/// Name of the interface with index `ifindex`, asking the kernel for that
+/// one link. Should the direct request fail for any reason but a missing
+/// device, the links are dumped and searched instead.
+pub fn link_name(socket: &mut Socket, ifindex: u32) -> io::Result<String> {
+    let payload = IfInfoMsg::new(0, ifindex as i32).bytes();
+    let hdr = NlMsgHeader::user_defined(RTM_GETLINK, payload.len() as u32);
+    let reply = socket
+        .send_and_recv(
+            Msg::new(hdr, Payload::Data(&payload)),
+            &NetlinkAddr::new(0, 0),
+        )
+        .and_then(
+            |(_, messages)| match messages.first().map(|m| m.payload()) {
+                Some(&Payload::Data(b)) => LinkInfo::from_bytes(b).map(|l| l.name),
+                Some(&Payload::Err(errno, _)) => Err(io::Error::from_raw_os_error(-errno)),
+                _ => Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "no RTM_NEWLINK reply",
+                )),
+            },
+        );
+
+    match reply {
+        Err(ref e) if e.raw_os_error() != Some(ENODEV) => list_links(socket)?
+            .into_iter()
+            .find(|l| l.index() == ifindex)
+            .map(|l| l.name)
+            .ok_or_else(|| io::Error::from_raw_os_error(ENODEV)),
+        reply => reply,
+    }
+}
+
+/// Same as `list_links`, keyed by interface index
+pub fn list_links_by_index(socket: &mut Socket) -> io::Result<HashMap<u32, LinkInfo>> {
+    let links = list_links(socket)?;
+    Ok(links.into_iter().map(|l| (l.index(), l)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Protocol;
+
+    // lets a test skip where NETLINK_ROUTE cannot be opened
+    fn route_socket() -> Option<Socket> {
+        Socket::try_new(Protocol::Route).unwrap()
+    }
+
+    fn rta(bytes: &mut Vec<u8>, ty: u16, value: &[u8]) {
+        bytes
+            .write_u16::<NativeEndian>((rta_header_length() + value.len()) as u16)
+            .unwrap();
+        bytes.write_u16::<NativeEndian>(ty).unwrap();
+        bytes.extend_from_slice(value);
+        let padded = rta_align(bytes.len());
+        bytes.resize(padded, 0);
+    }
+
+    fn u32_bytes(n: u32) -> Vec<u8> {
+        let mut b = vec![];
+        b.write_u32::<NativeEndian>(n).unwrap();
+        b
+    }
+
+    #[test]
+    fn test_link_attrs_decode() {
+        // the leading attributes of the loopback device in an RTM_NEWLINK
+        let mut bytes = vec![];
+        rta(&mut bytes, IFLA_IFNAME, b"lo\0");
+        rta(&mut bytes, IFLA_TXQLEN, &u32_bytes(1000));
+        rta(&mut bytes, IFLA_OPERSTATE, &[0]);
+        rta(&mut bytes, IFLA_LINKMODE, &[0]);
+        rta(&mut bytes, IFLA_MTU, &u32_bytes(65536));
+        rta(&mut bytes, IFLA_ADDRESS, &[0; 6]);
+        assert_eq!(bytes.len(), 8 + 8 + 8 + 8 + 8 + 12);
+
+        let attrs: Vec<RtAttr> = RtAttrIter::new(&bytes).collect::<io::Result<_>>().unwrap();
+        let types: Vec<u16> = attrs.iter().map(|a| a.ty()).collect();
+        assert_eq!(
+            types,
+            vec![
+                IFLA_IFNAME,
+                IFLA_TXQLEN,
+                IFLA_OPERSTATE,
+                IFLA_LINKMODE,
+                IFLA_MTU,
+                IFLA_ADDRESS
+            ]
+        );
+        assert_eq!(attrs[0].as_str().unwrap(), "lo");
+        assert_eq!(attrs[1].as_u32().unwrap(), 1000);
+        assert_eq!(attrs[2].value(), &[0]);
+        assert_eq!(attrs[4].as_u32().unwrap(), 65536);
+        assert_eq!(attrs[5].value(), &[0; 6]);
+    }
+
+    #[test]
+    fn test_ifinfomsg_decode() {
+        let mut bytes = vec![0u8, 0];
+        bytes.write_u16::<NativeEndian>(772).unwrap(); // ARPHRD_LOOPBACK
+        bytes.write_i32::<NativeEndian>(1).unwrap();
+        bytes.write_u32::<NativeEndian>(0x49).unwrap(); // UP|LOOPBACK|RUNNING
+        bytes.write_u32::<NativeEndian>(0).unwrap();
+        rta(&mut bytes, IFLA_IFNAME, b"lo\0");
+
+        let (info, attrs) = IfInfoMsg::from_bytes(&bytes).unwrap();
+        assert_eq!(info.link_type(), 772);
+        assert_eq!(info.index(), 1);
+        assert_eq!(info.flags(), 0x49);
+
+        let name = RtAttrIter::new(attrs).next().unwrap().unwrap();
+        assert_eq!(name.as_str().unwrap(), "lo");
+    }
+
+    #[test]
+    fn test_link_info_decode() {
+        let mut bytes = IfInfoMsg::new(0, 2).bytes();
+        rta(&mut bytes, IFLA_IFNAME, b"eth0\0");
+        rta(&mut bytes, IFLA_MTU, &u32_bytes(1500));
+        rta(&mut bytes, IFLA_ADDRESS, &[2, 0, 0, 0, 0, 1]);
+
+        let link = LinkInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(link.index(), 2);
+        assert_eq!(link.name(), "eth0");
+        assert_eq!(link.mtu(), Some(1500));
+        assert_eq!(link.address(), Some(&[2, 0, 0, 0, 0, 1][..]));
+
+        let bytes = IfInfoMsg::new(0, 2).bytes();
+        assert!(LinkInfo::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_addr_info_decode() {
+        // 10.0.0.1 peer 10.0.0.2/32 on a point-to-point link
+        let mut bytes = vec![2, 32, 0, 0];
+        bytes.extend_from_slice(&u32_bytes(5));
+        rta(&mut bytes, IFA_ADDRESS, &[10, 0, 0, 2]);
+        rta(&mut bytes, IFA_LOCAL, &[10, 0, 0, 1]);
+        rta(&mut bytes, IFA_LABEL, b"ppp0\0");
+
+        let addr = AddrInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(addr.family(), 2);
+        assert_eq!(addr.prefix_len(), 32);
+        assert_eq!(addr.index(), 5);
+        assert_eq!(addr.local(), Some(&[10, 0, 0, 1][..]));
+        assert_eq!(addr.peer(), Some(&[10, 0, 0, 2][..]));
+        assert_eq!(addr.label(), Some("ppp0"));
+
+        // a broadcast link repeats the local address
+        let mut bytes = vec![2, 24, 0, 0];
+        bytes.extend_from_slice(&u32_bytes(2));
+        rta(&mut bytes, IFA_ADDRESS, &[192, 168, 1, 7]);
+        rta(&mut bytes, IFA_LOCAL, &[192, 168, 1, 7]);
+        let addr = AddrInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(addr.local(), Some(&[192, 168, 1, 7][..]));
+        assert_eq!(addr.peer(), None);
+    }
+
+    #[test]
+    fn test_route_add_mode() {
+        // NLM_F_CREATE, NLM_F_EXCL, NLM_F_APPEND and NLM_F_REPLACE
+        assert_eq!(u16::from(RouteAddMode::Create), 0x400);
+        assert_eq!(u16::from(RouteAddMode::CreateExcl), 0x600);
+        assert_eq!(u16::from(RouteAddMode::Append), 0xc00);
+        assert_eq!(u16::from(RouteAddMode::Replace), 0x500);
+
+        let mut hdr = NlMsgHeader::user_defined(RTM_NEWROUTE, 0);
+        RouteAddMode::CreateExcl.apply(hdr.ack());
+        // NLM_F_REQUEST | NLM_F_ACK kept
+        assert_eq!(hdr.msg_flags(), 0x605);
+    }
+
+    #[test]
+    fn test_parse_linkinfo() {
+        // IFLA_LINKINFO of "ip link add link lo name v100 type vlan id 100"
+        let mut data = vec![];
+        // IFLA_VLAN_ID 100, then IFLA_VLAN_PROTOCOL 802.1Q in network order
+        rta(&mut data, 1, &100u16.to_ne_bytes());
+        rta(&mut data, 5, &0x8100u16.to_be_bytes());
+        let mut bytes = vec![];
+        rta(&mut bytes, IFLA_INFO_KIND, b"vlan\0");
+        rta(&mut bytes, IFLA_INFO_DATA, &data);
+
+        let info = parse_linkinfo(&bytes).unwrap();
+        assert_eq!(info.kind(), "vlan");
+        let data: Vec<RtAttr> = RtAttrIter::new(info.data())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0].ty(), 1);
+        assert_eq!(data[0].value(), &100u16.to_ne_bytes());
+        assert_eq!(data[1].value(), &[0x81, 0x00]);
+
+        assert!(parse_linkinfo(&bytes[12..]).is_err());
+    }
+
+    #[test]
+    fn test_link_name() {
+        let Some(mut socket) = route_socket() else {
+            return;
+        };
+        assert_eq!(link_name(&mut socket, 1).unwrap(), "lo");
+        let err = link_name(&mut socket, 0x7fff_fff0).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENODEV));
+    }
+
+    #[test]
+    fn test_parse_multipath() {
+        // "nexthop via 10.0.0.1 dev eth0 nexthop via 10.0.0.2 dev eth1 weight 2"
+        let mut bytes = vec![];
+        for &(ifindex, hops, last) in &[(2, 0, 1u8), (3, 1, 2)] {
+            let mut attrs = vec![];
+            rta(&mut attrs, RTA_GATEWAY, &[10, 0, 0, last]);
+            bytes
+                .write_u16::<NativeEndian>((RTNEXTHOP_LEN + attrs.len()) as u16)
+                .unwrap();
+            bytes.push(0);
+            bytes.push(hops);
+            bytes.write_i32::<NativeEndian>(ifindex).unwrap();
+            bytes.extend_from_slice(&attrs);
+        }
+        assert_eq!(bytes.len(), 2 * 16);
+
+        let hops = parse_multipath(&bytes).unwrap();
+        assert_eq!(hops.len(), 2);
+        assert_eq!(hops[0].ifindex(), 2);
+        assert_eq!(hops[0].gateway(), Some(&[10, 0, 0, 1][..]));
+        assert_eq!(hops[1].ifindex(), 3);
+        assert_eq!(hops[1].hops(), 1);
+        assert_eq!(hops[1].gateway(), Some(&[10, 0, 0, 2][..]));
+        assert_eq!(hops[1].oif(), None);
+
+        assert!(parse_multipath(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn test_link_dump_payload() {
+        let payload = link_dump_payload(RTEXT_FILTER_VF | RTEXT_FILTER_SKIP_STATS);
+        // ifinfomsg, then the mask
+        assert_eq!(payload.len(), 16 + 8);
+        let mut expected = vec![];
+        rta(&mut expected, IFLA_EXT_MASK, &u32_bytes(9));
+        assert_eq!(&payload[16..], &expected[..]);
+
+        let Some(mut socket) = route_socket() else {
+            return;
+        };
+        let links = list_links_with_mask(&mut socket, RTEXT_FILTER_SKIP_STATS).unwrap();
+        assert_eq!(links[0].name(), "lo");
+    }
+
+    #[test]
+    fn test_list_links_by_index() {
+        let Some(mut socket) = route_socket() else {
+            return;
+        };
+        let links = list_links_by_index(&mut socket).unwrap();
+        // loopback is the first device of every network namespace
+        assert_eq!(links[&1].name(), "lo");
+    }
+
+    #[test]
+    fn test_link_stats64_decode() {
+        let mut bytes = vec![];
+        for n in 0..25u64 {
+            bytes.write_u64::<NativeEndian>(n * 10).unwrap();
+        }
+        // current kernels append rx_otherhost_dropped
+        let stats = RtnlLinkStats64::from_bytes(&bytes).unwrap();
+        assert_eq!(stats.rx_packets(), 0);
+        assert_eq!(stats.tx_packets(), 10);
+        assert_eq!(stats.rx_bytes(), 20);
+        assert_eq!(stats.tx_bytes(), 30);
+        assert_eq!(stats.multicast(), 80);
+        assert_eq!(stats.rx_nohandler(), 230);
+
+        assert!(RtnlLinkStats64::from_bytes(&bytes[..100]).is_err());
+    }
+
+    #[test]
+    fn test_ifa_cacheinfo_decode() {
+        let mut bytes = vec![];
+        bytes.write_u32::<NativeEndian>(u32::MAX).unwrap();
+        bytes.write_u32::<NativeEndian>(3600).unwrap();
+        bytes.write_u32::<NativeEndian>(150).unwrap();
+        bytes.write_u32::<NativeEndian>(275).unwrap();
+
+        let info = IfaCacheInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(info.prefered(), u32::MAX);
+        assert_eq!(info.valid(), 3600);
+        assert_eq!(info.cstamp(), 150);
+        assert_eq!(info.tstamp(), 275);
+
+        bytes.push(0);
+        assert!(IfaCacheInfo::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_truncated_attr() {
+        let mut bytes = vec![];
+        rta(&mut bytes, IFLA_MTU, &u32_bytes(1500));
+        let mut iter = RtAttrIter::new(&bytes[..7]);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+}
@@ -24,6 +24,32 @@ impl NetlinkAddr {
         })
     }
 
+    /// The socket bound to `pid`, without multicast groups
+    pub fn unicast(pid: u32) -> NetlinkAddr {
+        NetlinkAddr::new(pid, 0)
+    }
+
+    /// The kernel, which always has pid 0
+    pub fn kernel() -> NetlinkAddr {
+        NetlinkAddr::new(0, 0)
+    }
+
+    /// Destination addressing multicast `group`. Groups outside 1 to 32 do
+    /// not fit the `nl_groups` mask and fail with `InvalidInput`.
+    ///
+    /// The kernel also delivers the message to pid 0, so for protocols
+    /// without a kernel socket, such as `Protocol::Usersock`, the send
+    /// reports ECONNREFUSED after the group has received it.
+    pub fn multicast(group: u32) -> io::Result<NetlinkAddr> {
+        if !(1..=32).contains(&group) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("multicast group {} does not fit the group mask", group),
+            ));
+        }
+        Ok(NetlinkAddr::new(0, 1 << (group - 1)))
+    }
+
     pub fn pid(&self) -> u32 {
         self.0.nl_pid
     }
@@ -86,4 +112,27 @@ mod tests {
         assert_eq!(nladdr.pid(), nl2.pid());
         assert_eq!(nladdr.groups(), nl2.groups());
     }
+
+    #[test]
+    fn unicast_addr() {
+        let addr = NetlinkAddr::unicast(4242);
+        assert_eq!(addr.pid(), 4242);
+        assert_eq!(addr.groups(), 0);
+        assert_eq!(NetlinkAddr::kernel(), NetlinkAddr::new(0, 0));
+    }
+
+    #[test]
+    fn multicast_addr() {
+        assert_eq!(NetlinkAddr::multicast(1).unwrap().groups(), 1);
+        assert_eq!(NetlinkAddr::multicast(32).unwrap().groups(), 0x8000_0000);
+        for group in &[0, 33] {
+            let err = NetlinkAddr::multicast(*group).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+
+        let sa = NetlinkAddr::multicast(3).unwrap().as_sockaddr();
+        let nladdr = sockaddr_to_netlinkaddr(&sa).unwrap();
+        assert_eq!(nladdr.pid(), 0);
+        assert_eq!(nladdr.groups(), 4);
+    }
 }
@@ -0,0 +1,471 @@
+use byteorder::{BigEndian, NativeEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::HashMap;
+use std::io::{self, Cursor, ErrorKind};
+use std::str;
+
+// #define NLA_ALIGNTO     4
+const NLA_ALIGNTO: usize = 4;
+
+// #define NLA_ALIGN(len)  (((len) + NLA_ALIGNTO - 1) & ~(NLA_ALIGNTO - 1))
+#[inline]
+fn nla_align(len: usize) -> usize {
+    (len + (NLA_ALIGNTO - 1)) & !(NLA_ALIGNTO - 1)
+}
+
+// #define NLA_HDRLEN      ((int) NLA_ALIGN(sizeof(struct nlattr)))
+#[inline]
+fn nla_header_length() -> usize {
+    nla_align(4)
+}
+
+// flags in the high bits of nla_type
+pub const NLA_F_NESTED: u16 = 1 << 15;
+pub const NLA_F_NET_BYTEORDER: u16 = 1 << 14;
+
+// ATTRIBUTE FORMAT
+// __u16 nla_len;     /* Length of attribute including header. */
+// __u16 nla_type;    /* Type of attribute. */
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct NlAttr<'a> {
+    // as on the wire, with the flag bits
+    ty: u16,
+    value: &'a [u8],
+}
+
+impl<'a> NlAttr<'a> {
+    /// Decodes one attribute, returning it with the number of bytes it
+    /// occupies including trailing padding.
+    pub fn from_bytes(bytes: &'a [u8]) -> io::Result<(NlAttr<'a>, usize)> {
+        let mut cursor = Cursor::new(bytes);
+        let len = cursor.read_u16::<NativeEndian>()? as usize;
+        let ty = cursor.read_u16::<NativeEndian>()?;
+
+        if len < nla_header_length() || len > bytes.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid attribute length",
+            ));
+        }
+
+        let attr = NlAttr {
+            ty,
+            value: &bytes[nla_header_length()..len],
+        };
+        Ok((attr, nla_align(len).min(bytes.len())))
+    }
+
+    /// Attribute type without `NLA_F_NET_BYTEORDER`, still carrying
+    /// `NLA_F_NESTED`; match on `kind` instead
+    pub fn ty(&self) -> u16 {
+        self.ty & !NLA_F_NET_BYTEORDER
+    }
+
+    /// Attribute type with the `NLA_F_NESTED` and `NLA_F_NET_BYTEORDER`
+    /// flags cleared
+    pub fn kind(&self) -> u16 {
+        self.ty & !(NLA_F_NESTED | NLA_F_NET_BYTEORDER)
+    }
+
+    /// Whether the type carries `NLA_F_NESTED`. Not every subsystem sets
+    /// it on nested attributes.
+    pub fn is_nested(&self) -> bool {
+        self.ty & NLA_F_NESTED != 0
+    }
+
+    /// Whether the type carries `NLA_F_NET_BYTEORDER`, see `as_u16`
+    pub fn is_net_byteorder(&self) -> bool {
+        self.ty & NLA_F_NET_BYTEORDER != 0
+    }
+
+    pub fn value(&self) -> &'a [u8] {
+        self.value
+    }
+
+    /// Iterates the attributes nested inside this one
+    pub fn nested(&self) -> AttrIter<'a> {
+        AttrIter::new(self.value)
+    }
+
+    /// Value in host byte order, read as big endian if the attribute is
+    /// flagged `NLA_F_NET_BYTEORDER`
+    pub fn as_u16(&self) -> io::Result<u16> {
+        let mut value = self.fixed(2)?;
+        if self.is_net_byteorder() {
+            value.read_u16::<BigEndian>()
+        } else {
+            value.read_u16::<NativeEndian>()
+        }
+    }
+
+    /// Like `as_u16`
+    pub fn as_u32(&self) -> io::Result<u32> {
+        let mut value = self.fixed(4)?;
+        if self.is_net_byteorder() {
+            value.read_u32::<BigEndian>()
+        } else {
+            value.read_u32::<NativeEndian>()
+        }
+    }
+
+    /// Value as a string, without the trailing NUL
+    pub fn as_str(&self) -> io::Result<&'a str> {
+        let value = match self.value.iter().position(|&b| b == 0) {
+            Some(n) => &self.value[..n],
+            None => self.value,
+        };
+        str::from_utf8(value).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn fixed(&self, size: usize) -> io::Result<&'a [u8]> {
+        if self.value.len() != size {
+            Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "attribute value has unexpected size",
+            ))
+        } else {
+            Ok(self.value)
+        }
+    }
+}
+
+/// Iterator over a region of consecutive attributes
+#[derive(Clone, Debug)]
+pub struct AttrIter<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> AttrIter<'a> {
+    pub fn new(bytes: &'a [u8]) -> AttrIter<'a> {
+        AttrIter { bytes }
+    }
+
+    /// Splits the attributes into those with a type in `known` and the
+    /// types of the others, in the order seen, to spot what a newer kernel
+    /// sends that the caller does not handle yet.
+    pub fn collect_known(self, known: &[u16]) -> io::Result<(Vec<NlAttr<'a>>, Vec<u16>)> {
+        let mut attrs = vec![];
+        let mut unknown = vec![];
+        for attr in self {
+            let attr = attr?;
+            if known.contains(&attr.kind()) {
+                attrs.push(attr);
+            } else {
+                unknown.push(attr.kind());
+            }
+        }
+        Ok((attrs, unknown))
+    }
+}
+
+impl<'a> Iterator for AttrIter<'a> {
+    type Item = io::Result<NlAttr<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<NlAttr<'a>>> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        match NlAttr::from_bytes(self.bytes) {
+            Ok((attr, n)) => {
+                self.bytes = &self.bytes[n..];
+                Some(Ok(attr))
+            }
+            Err(e) => {
+                // stop after reporting a malformed attribute
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Value of the first attribute of type `ty` in `bytes`. Scanning stops at
+/// a malformed attribute.
+pub fn find_attr(bytes: &[u8], ty: u16) -> Option<&[u8]> {
+    AttrIter::new(bytes)
+        .map_while(Result::ok)
+        .find(|attr| attr.kind() == ty)
+        .map(|attr| attr.value())
+}
+
+/// Serializes attributes into a contiguous, aligned region
+#[derive(Clone, Default, Debug)]
+pub struct AttrBuilder {
+    bytes: Vec<u8>,
+}
+
+impl AttrBuilder {
+    pub fn new() -> AttrBuilder {
+        AttrBuilder { bytes: vec![] }
+    }
+
+    /// Append an attribute with a raw value
+    pub fn put(&mut self, ty: u16, value: &[u8]) -> &mut AttrBuilder {
+        let len = nla_header_length() + value.len();
+        // writing into a Vec cannot fail
+        self.bytes.write_u16::<NativeEndian>(len as u16).unwrap();
+        self.bytes.write_u16::<NativeEndian>(ty).unwrap();
+        self.bytes.extend_from_slice(value);
+        let padded = nla_align(self.bytes.len());
+        self.bytes.resize(padded, 0);
+        self
+    }
+
+    pub fn put_u16(&mut self, ty: u16, value: u16) -> &mut AttrBuilder {
+        let mut b = vec![];
+        b.write_u16::<NativeEndian>(value).unwrap();
+        self.put(ty, &b)
+    }
+
+    pub fn put_u32(&mut self, ty: u16, value: u32) -> &mut AttrBuilder {
+        let mut b = vec![];
+        b.write_u32::<NativeEndian>(value).unwrap();
+        self.put(ty, &b)
+    }
+
+    /// Append a NUL terminated string attribute
+    pub fn put_str(&mut self, ty: u16, value: &str) -> &mut AttrBuilder {
+        let mut b: Vec<u8> = value.into();
+        b.push(0);
+        self.put(ty, &b)
+    }
+
+    /// Append the attributes of `nested` as a single attribute
+    pub fn put_nested(&mut self, ty: u16, nested: &AttrBuilder) -> &mut AttrBuilder {
+        self.put(ty, nested.bytes())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// What the value of an attribute must hold to pass `validate`
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AttrKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    /// UTF-8 text, optionally NUL terminated
+    String,
+    /// A region of well formed attributes
+    Nested,
+    /// Exactly this many bytes
+    Bytes(usize),
+}
+
+/// Expected kinds by attribute type, like the kernel's `nla_policy`.
+/// Attribute types without an entry are accepted as is.
+#[derive(Clone, Default, Debug)]
+pub struct AttrPolicy {
+    kinds: HashMap<u16, AttrKind>,
+}
+
+impl AttrPolicy {
+    pub fn new() -> AttrPolicy {
+        AttrPolicy {
+            kinds: HashMap::new(),
+        }
+    }
+
+    pub fn expect(&mut self, ty: u16, kind: AttrKind) -> &mut AttrPolicy {
+        self.kinds.insert(ty, kind);
+        self
+    }
+
+    pub fn kind(&self, ty: u16) -> Option<AttrKind> {
+        self.kinds.get(&ty).cloned()
+    }
+}
+
+/// Checks every attribute in `attrs` against `policy`
+pub fn validate(attrs: &[u8], policy: &AttrPolicy) -> io::Result<()> {
+    for attr in AttrIter::new(attrs) {
+        let attr = attr?;
+        let valid = match policy.kind(attr.kind()) {
+            None => true,
+            Some(AttrKind::U8) => attr.value().len() == 1,
+            Some(AttrKind::U16) => attr.value().len() == 2,
+            Some(AttrKind::U32) => attr.value().len() == 4,
+            Some(AttrKind::U64) => attr.value().len() == 8,
+            Some(AttrKind::String) => attr.as_str().is_ok(),
+            Some(AttrKind::Nested) => attr.nested().all(|a| a.is_ok()),
+            Some(AttrKind::Bytes(len)) => attr.value().len() == len,
+        };
+        if !valid {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("attribute {} does not match policy", attr.kind()),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_round_trip() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7).put_str(2, "eth0").put_u16(3, 9);
+        // "eth0\0" pads to 8 bytes
+        assert_eq!(attrs.bytes().len(), 8 + 12 + 8);
+
+        let parsed: Vec<NlAttr> = AttrIter::new(attrs.bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed[0].ty(), 1);
+        assert_eq!(parsed[0].as_u32().unwrap(), 7);
+        assert_eq!(parsed[1].as_str().unwrap(), "eth0");
+        assert_eq!(parsed[2].as_u16().unwrap(), 9);
+    }
+
+    #[test]
+    fn test_attr_net_byteorder() {
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put(NLA_F_NET_BYTEORDER | 1, &0x0a00_0001u32.to_be_bytes())
+            .put(NLA_F_NET_BYTEORDER | 2, &8080u16.to_be_bytes())
+            .put(3, &0x0a00_0001u32.to_ne_bytes());
+
+        let parsed: Vec<NlAttr> = AttrIter::new(attrs.bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(parsed[0].ty(), 1);
+        assert_eq!(parsed[0].as_u32().unwrap(), 0x0a00_0001);
+        assert_eq!(parsed[1].ty(), 2);
+        assert_eq!(parsed[1].as_u16().unwrap(), 8080);
+        assert_eq!(parsed[2].ty(), 3);
+        assert_eq!(parsed[2].as_u32().unwrap(), 0x0a00_0001);
+    }
+
+    #[test]
+    fn test_attr_kind_flags() {
+        let both = NLA_F_NESTED | NLA_F_NET_BYTEORDER;
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put(7, &[])
+            .put(NLA_F_NESTED | 7, &[])
+            .put(NLA_F_NET_BYTEORDER | 7, &[])
+            .put(both | 7, &[]);
+
+        let parsed: Vec<NlAttr> = AttrIter::new(attrs.bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        let flags: Vec<(u16, bool, bool)> = parsed
+            .iter()
+            .map(|a| (a.kind(), a.is_nested(), a.is_net_byteorder()))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                (7, false, false),
+                (7, true, false),
+                (7, false, true),
+                (7, true, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attr_nested() {
+        let mut inner = AttrBuilder::new();
+        inner.put_u32(1, 42);
+        let mut outer = AttrBuilder::new();
+        outer.put_nested(5, &inner);
+
+        let attr = AttrIter::new(outer.bytes()).next().unwrap().unwrap();
+        assert_eq!(attr.ty(), 5);
+        let nested = attr.nested().next().unwrap().unwrap();
+        assert_eq!(nested.as_u32().unwrap(), 42);
+    }
+
+    #[test]
+    fn test_find_attr() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7).put_str(3, "eth0").put_str(3, "eth1");
+
+        assert_eq!(find_attr(attrs.bytes(), 3), Some(&b"eth0\0"[..]));
+        assert_eq!(find_attr(attrs.bytes(), 2), None);
+
+        // as nla_nest_start flags it
+        let mut inner = AttrBuilder::new();
+        inner.put_u32(1, 42);
+        let mut nested = AttrBuilder::new();
+        nested.put(NLA_F_NESTED | 4, inner.bytes());
+        assert_eq!(find_attr(nested.bytes(), 4), Some(inner.bytes()));
+        let (known, unknown) = AttrIter::new(nested.bytes()).collect_known(&[4]).unwrap();
+        assert_eq!(known.len(), 1);
+        assert!(unknown.is_empty());
+    }
+
+    #[test]
+    fn test_collect_known() {
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_u32(1, 7)
+            .put_u16(40, 1)
+            .put_str(2, "eth0")
+            .put(41, &[]);
+
+        let (known, unknown) = AttrIter::new(attrs.bytes())
+            .collect_known(&[1, 2, 3])
+            .unwrap();
+        assert_eq!(known.len(), 2);
+        assert_eq!(known[0].as_u32().unwrap(), 7);
+        assert_eq!(known[1].as_str().unwrap(), "eth0");
+        assert_eq!(unknown, vec![40, 41]);
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        let mut policy = AttrPolicy::new();
+        policy
+            .expect(1, AttrKind::U32)
+            .expect(2, AttrKind::String)
+            .expect(3, AttrKind::Nested)
+            .expect(4, AttrKind::Bytes(6));
+
+        let mut inner = AttrBuilder::new();
+        inner.put_u16(1, 5);
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_u32(1, 7)
+            .put_str(2, "eth0")
+            .put_nested(3, &inner)
+            .put(4, &[0; 6])
+            .put(9, &[1, 2, 3]);
+        validate(attrs.bytes(), &policy).unwrap();
+
+        let mut wrong_size = AttrBuilder::new();
+        wrong_size.put_u16(1, 7);
+        assert!(validate(wrong_size.bytes(), &policy).is_err());
+
+        let mut bad_utf8 = AttrBuilder::new();
+        bad_utf8.put(2, &[0xff, 0xfe, 0]);
+        assert!(validate(bad_utf8.bytes(), &policy).is_err());
+
+        let mut bad_nested = AttrBuilder::new();
+        bad_nested.put(3, &[8, 0, 1]);
+        assert!(validate(bad_nested.bytes(), &policy).is_err());
+
+        let mut short = AttrBuilder::new();
+        short.put(4, &[0; 4]);
+        assert!(validate(short.bytes(), &policy).is_err());
+    }
+
+    #[test]
+    fn test_attr_invalid_length() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7);
+        let bytes = &attrs.bytes()[..6];
+
+        let mut iter = AttrIter::new(bytes);
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+    }
+}
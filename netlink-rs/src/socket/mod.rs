@@ -3,29 +3,79 @@ mod socket_impl;
 
 mod address;
 pub use self::address::*;
+mod attr;
+pub use self::attr::*;
 mod msg;
 pub use self::msg::*;
+mod ring;
+use self::ring::*;
 
 use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
-use libc::{c_int, AF_NETLINK, SOCK_RAW};
+use libc::{
+    c_int, AF_NETLINK, CLONE_NEWNET, EACCES, EAFNOSUPPORT, EIO, ENOBUFS, ENOTTY, EPERM,
+    EPROTONOSUPPORT, MSG_DONTWAIT, MSG_PEEK, MSG_TRUNC, NETLINK_ADD_MEMBERSHIP,
+    NETLINK_DROP_MEMBERSHIP, NETLINK_LISTEN_ALL_NSID, NETLINK_LIST_MEMBERSHIPS, SOCK_CLOEXEC,
+    SOCK_NONBLOCK, SOCK_RAW, SOL_NETLINK, SOL_SOCKET, SO_DOMAIN, SO_ERROR, SO_PRIORITY,
+    SO_PROTOCOL, SO_RCVBUF, SO_REUSEADDR, SO_SNDBUF, TIOCOUTQ,
+};
 use socket::socket_impl::Socket as SocketImpl;
-use std::convert::Into;
-use std::io::{self, Cursor, Write};
-use std::iter::repeat;
+use std::cell::Cell;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::convert::{Into, TryFrom};
+use std::error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Cursor, ErrorKind, IoSlice, IoSliceMut, Read, Write};
 use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::process;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 // #define NLMSG_ALIGNTO   4
 const NLMSG_ALIGNTO: usize = 4;
 
+/// A receive buffer of this size holds any datagram the kernel sends in a
+/// dump, which it caps at 32 KiB. A guideline rather than a kernel limit:
+/// unicasts between sockets are bounded by their buffer sizes instead, see
+/// `Socket::max_msg_size`.
+pub const MAX_NETLINK_MSG_SIZE: usize = 32768;
+
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Payload<'a> {
     None,
     Data(&'a [u8]),
     Ack(NlMsgHeader),
     Err(c_int, NlMsgHeader),
+    /// Status of the `Done` message ending a dump: 0, or the negated errno
+    /// of the error that cut the dump short
+    Done(c_int),
 }
 
 impl<'a> Payload<'a> {
+    /// The errno of an `Err` payload, or of a `Done` payload with a
+    /// nonzero status, as a positive number. The kernel sends, and the
+    /// payload stores, the negated value, e.g. -1 for EPERM.
+    pub fn errno(&self) -> Option<c_int> {
+        match *self {
+            Payload::Err(errno, _) => Some(-errno),
+            Payload::Done(status) if status != 0 => Some(-status),
+            _ => None,
+        }
+    }
+
+    /// Error reply to `request` reporting `e`, the inverse of
+    /// `Msg::as_os_error`. Errors without an OS errno are reported as EIO.
+    pub fn from_io_error(e: &io::Error, request: NlMsgHeader) -> Payload<'a> {
+        let errno = match e.raw_os_error() {
+            Some(errno) if errno > 0 => errno,
+            _ => EIO,
+        };
+        Payload::Err(-errno, request)
+    }
+
     fn data(bytes: &'a [u8], len: usize) -> io::Result<(Payload<'a>, usize)> {
         use std::io::{Error, ErrorKind};
 
@@ -40,18 +90,51 @@ impl<'a> Payload<'a> {
         }
     }
 
-    fn nlmsg_error(bytes: &'a [u8]) -> io::Result<(Payload<'a>, usize)> {
+    /// Parses the body of an NLMSG_ERROR message with header `hdr`, along
+    /// with the extended ACK attributes its flags announce. The whole body is
+    /// consumed, as it may hold more than the embedded header.
+    fn nlmsg_error(
+        bytes: &'a [u8],
+        hdr: &NlMsgHeader,
+        config: &SocketConfig,
+    ) -> io::Result<(Payload<'a>, Option<ExtAck<'a>>, usize)> {
+        if bytes.len() < size_of::<c_int>() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "error message without an error code",
+            ));
+        }
         let mut cursor = Cursor::new(bytes);
         // the error field is of type c_int, but we lack proper ways of reading
         // that. FIXME: implement proper checks to ensure that c_int == i32
         let err = cursor.read_i32::<NativeEndian>()?;
         let n = cursor.position() as usize;
-        let (hdr, n2) = NlMsgHeader::from_bytes(&bytes[n..])?;
-        let num = n + n2;
-        if err == 0 {
-            Ok((Payload::Ack(hdr), num))
+        let (req, _) = NlMsgHeader::from_bytes(&bytes[n..])?;
+        let ext_ack = if hdr.has_ack_tlvs() {
+            Some(ExtAck::from_error_body(
+                bytes,
+                &req,
+                hdr.is_capped(),
+                config,
+            )?)
         } else {
-            Ok((Payload::Err(err, hdr), num))
+            None
+        };
+        let payload = if err == 0 {
+            Payload::Ack(req)
+        } else {
+            Payload::Err(err, req)
+        };
+        Ok((payload, ext_ack, bytes.len()))
+    }
+
+    /// Serialized length, without trailing padding
+    fn len(&self) -> usize {
+        match *self {
+            Payload::None => 0,
+            Payload::Data(b) => b.len(),
+            Payload::Ack(_) | Payload::Err(..) => size_of::<c_int>() + size_of::<NlMsgHeader>(),
+            Payload::Done(_) => size_of::<c_int>(),
         }
     }
 
@@ -61,7 +144,8 @@ impl<'a> Payload<'a> {
             Payload::Data(b) => Ok(b.into()),
             Payload::Ack(h) => {
                 let mut vec = vec![];
-                vec.write_u32::<NativeEndian>(0)?;
+                // same c_int error field as Err, holding zero
+                vec.write_i32::<NativeEndian>(0)?;
                 let _ = vec.write(h.bytes())?;
                 Ok(vec)
             }
@@ -71,7 +155,103 @@ impl<'a> Payload<'a> {
                 let _ = vec.write(h.bytes())?;
                 Ok(vec)
             }
+            Payload::Done(status) => {
+                let mut vec = vec![];
+                vec.write_i32::<NativeEndian>(status)?;
+                Ok(vec)
+            }
+        }
+    }
+}
+
+/// What an error or ACK message embeds of the request it answers
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum ErrorLayout {
+    /// Only the request's header, as with `NETLINK_CAP_ACK` and for ACKs
+    Capped,
+    /// The whole request, header and payload
+    Full,
+}
+
+impl ErrorLayout {
+    // NLM_F_CAPPED settles it, but kernels before 4.12 cap without
+    // setting it, so otherwise the request must fit in the body
+    fn detect(hdr: &NlMsgHeader, request: &NlMsgHeader) -> ErrorLayout {
+        let body_len = (hdr.msg_length() as usize).saturating_sub(nlmsg_header_length());
+        if !hdr.is_capped() && body_len >= size_of::<c_int>() + request.msg_length() as usize {
+            ErrorLayout::Full
+        } else {
+            ErrorLayout::Capped
+        }
+    }
+}
+
+// Extended ACK attributes, following the embedded request of an error
+// #define NLMSGERR_ATTR_MSG     1   /* error message string (string) */
+// #define NLMSGERR_ATTR_OFFS    2   /* offset of the invalid attribute (u32) */
+// #define NLMSGERR_ATTR_COOKIE  3   /* arbitrary subsystem specific cookie */
+const NLMSGERR_ATTR_MSG: u16 = 1;
+const NLMSGERR_ATTR_OFFS: u16 = 2;
+const NLMSGERR_ATTR_COOKIE: u16 = 3;
+
+/// Extended ACK attributes the kernel appends to an error or ACK message
+/// when the socket has `NETLINK_EXT_ACK` enabled
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct ExtAck<'a> {
+    message: Option<&'a str>,
+    offset: Option<u32>,
+    cookie: Option<&'a [u8]>,
+}
+
+impl<'a> ExtAck<'a> {
+    /// Parses the attributes of an NLMSG_ERROR body, which starts with the
+    /// error code and the header of the request. Unless the reply is capped
+    /// the request's payload sits between that header and the attributes.
+    fn from_error_body(
+        bytes: &'a [u8],
+        request: &NlMsgHeader,
+        capped: bool,
+        config: &SocketConfig,
+    ) -> io::Result<ExtAck<'a>> {
+        let request_len = if capped {
+            config.header_length()
+        } else {
+            config.align(request.msg_length() as usize)
+        };
+        let start = size_of::<c_int>() + request_len;
+        if start > bytes.len() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "extended ACK attributes out of bounds",
+            ));
+        }
+
+        let mut ext_ack = ExtAck::default();
+        for attr in AttrIter::new(&bytes[start..]) {
+            let attr = attr?;
+            match attr.kind() {
+                NLMSGERR_ATTR_MSG => ext_ack.message = Some(attr.as_str()?),
+                NLMSGERR_ATTR_OFFS => ext_ack.offset = Some(attr.as_u32()?),
+                NLMSGERR_ATTR_COOKIE => ext_ack.cookie = Some(attr.value()),
+                _ => {}
+            }
         }
+        Ok(ext_ack)
+    }
+
+    /// Human readable error message
+    pub fn message(&self) -> Option<&'a str> {
+        self.message
+    }
+
+    /// Offset of the offending attribute within the request
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
+
+    /// Opaque bytes some subsystems (e.g. tc) attach to correlate replies
+    pub fn cookie(&self) -> Option<&'a [u8]> {
+        self.cookie
     }
 }
 
@@ -79,10 +259,16 @@ impl<'a> Payload<'a> {
 pub struct Msg<'a> {
     header: NlMsgHeader,
     payload: Payload<'a>,
+    ext_ack: Option<ExtAck<'a>>,
 }
 
 impl<'a> Msg<'a> {
     pub fn from_bytes(bytes: &'a [u8]) -> io::Result<(Msg<'a>, usize)> {
+        Msg::from_bytes_with(bytes, &SocketConfig::new())
+    }
+
+    /// Like `from_bytes`, for messages framed with `config`'s alignment
+    pub fn from_bytes_with(bytes: &'a [u8], config: &SocketConfig) -> io::Result<(Msg<'a>, usize)> {
         let (hdr, n) = NlMsgHeader::from_bytes(bytes)?;
 
         // message length is total length minus header size
@@ -93,15 +279,30 @@ impl<'a> Msg<'a> {
         if !(n <= end && end <= bytes.len()) {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidData,
-                "invalid message length",
+                format!(
+                    "invalid message length {} with {} bytes available: {}",
+                    end,
+                    bytes.len(),
+                    hex_prefix(bytes)
+                ),
             ));
         }
 
+        let mut ext_ack = None;
         let (payload, n2) = match hdr.msg_type() {
+            // the kernel always sends the status, other senders may not
+            MsgType::Done if end - n >= size_of::<c_int>() => {
+                let status = (&bytes[n..end]).read_i32::<NativeEndian>()?;
+                (Payload::Done(status), size_of::<c_int>())
+            }
             MsgType::Done => (Payload::None, 0),
-            MsgType::Error => Payload::nlmsg_error(&bytes[n..end])?,
+            MsgType::Error => {
+                let (payload, tlvs, n2) = Payload::nlmsg_error(&bytes[n..end], &hdr, config)?;
+                ext_ack = tlvs;
+                (payload, n2)
+            }
             _ => {
-                let msg_len = hdr.msg_length() as usize - nlmsg_header_length();
+                let msg_len = hdr.msg_length() as usize - config.header_length();
                 Payload::data(&bytes[n..], msg_len)?
             }
         };
@@ -110,6 +311,7 @@ impl<'a> Msg<'a> {
             Msg {
                 header: hdr,
                 payload,
+                ext_ack,
             },
             n + n2,
         ))
@@ -119,7 +321,60 @@ impl<'a> Msg<'a> {
         Msg {
             header: hdr,
             payload,
+            ext_ack: None,
+        }
+    }
+
+    /// A message of type `ty` carrying `attrs`, such as the bytes of an
+    /// `AttrBuilder`, with the header length set to match
+    pub fn with_attrs(ty: MsgType, flags: u16, seq: u32, pid: u32, attrs: &'a [u8]) -> Msg<'a> {
+        let mut hdr = NlMsgHeader::user_defined(ty.into(), attrs.len() as u32);
+        hdr.flags(flags).seq(seq).pid(pid);
+        Msg::new(hdr, Payload::Data(attrs))
+    }
+
+    /// A dump request (`NLM_F_REQUEST | NLM_F_DUMP`) of type `ty` carrying
+    /// `attrs`, e.g. to filter the dump
+    pub fn dump_request(ty: MsgType, attrs: &'a [u8]) -> Msg<'a> {
+        let mut hdr = NlMsgHeader::user_defined(ty.into(), attrs.len() as u32);
+        hdr.dump();
+        Msg::new(hdr, Payload::Data(attrs))
+    }
+
+    /// Error reply to `request` reporting the positive `errno`, as the
+    /// kernel builds it. With `cap_ack`, as after `NETLINK_CAP_ACK`, only
+    /// the request's header is embedded and the reply is flagged
+    /// `NLM_F_CAPPED`; otherwise its payload follows the header.
+    pub fn error(request: &Msg, errno: c_int, cap_ack: bool) -> io::Result<OwnedMsg> {
+        Msg::reply(request, -errno, cap_ack)
+    }
+
+    /// ACK of `request`. The kernel embeds only the request's header in an
+    /// ACK whether or not `NETLINK_CAP_ACK` is set, flagged `NLM_F_CAPPED`.
+    pub fn ack(request: &Msg) -> io::Result<OwnedMsg> {
+        Msg::reply(request, 0, true)
+    }
+
+    fn reply(request: &Msg, errno: c_int, capped: bool) -> io::Result<OwnedMsg> {
+        let req = request.header();
+        let mut body = vec![];
+        body.write_i32::<NativeEndian>(errno)?;
+        body.extend_from_slice(req.bytes());
+        if !capped {
+            body.append(&mut request.payload.bytes()?);
         }
+
+        let mut hdr = NlMsgHeader::error();
+        hdr.data_length(body.len() as u32)
+            .seq(req.msg_seq())
+            .pid(req.msg_pid());
+        if capped {
+            hdr.capped();
+        }
+        let mut bytes: Vec<u8> = hdr.bytes().into();
+        bytes.append(&mut body);
+        let (reply, _) = OwnedMsg::from_bytes(&bytes)?;
+        Ok(reply)
     }
 
     pub fn bytes(&self) -> io::Result<Vec<u8>> {
@@ -133,232 +388,3415 @@ impl<'a> Msg<'a> {
         self.header
     }
 
+    /// Number of bytes this message occupies in a datagram holding several
+    /// messages, including the padding that aligns the next one.
+    pub fn wire_len(&self) -> usize {
+        SocketConfig::new().wire_len(self)
+    }
+
     pub fn payload(&self) -> &Payload<'a> {
         &self.payload
     }
-}
 
-// #[repr(C)]
-// #[derive(Clone, Copy, Eq, PartialEq, Debug)]
-// struct NlErr {
-//     /// 0 if used as acknowledgement
-//     err: u32,
-//     /// Msg header that caused the error
-//     hdr: NlMsgHeader,
-// }
+    /// The bytes of a `Payload::Data` body, e.g. attributes for `AttrIter`,
+    /// without copying the header
+    pub fn payload_bytes(&self) -> io::Result<&'a [u8]> {
+        match self.payload {
+            Payload::Data(b) => Ok(b),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "message payload is not data",
+            )),
+        }
+    }
 
-pub struct Socket {
-    inner: SocketImpl,
-    buf: Vec<u8>,
-}
+    /// Rejects a request with sequence number 0.
+    ///
+    /// The kernel sends its multicast notifications with seq 0, so a reply
+    /// to such a request cannot be told apart from an unrelated event.
+    /// Sending does not call this; it is a check for callers that want it.
+    pub fn validate_request(&self) -> io::Result<()> {
+        if self.header.is_request() && self.header.msg_seq() == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "request has sequence number 0, which notifications use",
+            ));
+        }
+        Ok(())
+    }
 
-impl Socket {
-    pub fn new<P: Into<i32>>(protocol: P) -> io::Result<Socket> {
-        let s = SocketImpl::new(AF_NETLINK, SOCK_RAW, protocol.into())?;
-        let bytes = 4096;
-        let mut buf = Vec::with_capacity(bytes);
-        buf.extend(repeat(0u8).take(bytes));
-        Ok(Socket { inner: s, buf })
+    /// The error an `NLMSG_ERROR` message reports, `None` for an ACK or any
+    /// other message. Netlink carries the errno negated.
+    pub fn as_os_error(&self) -> Option<io::Error> {
+        self.payload.errno().map(io::Error::from_raw_os_error)
     }
 
-    pub fn bind(&self, addr: NetlinkAddr) -> io::Result<()> {
-        self.inner.bind(&addr.as_sockaddr())
+    /// Extended ACK attributes of an error or ACK message, if the kernel
+    /// included any
+    pub fn ext_ack(&self) -> Option<&ExtAck<'a>> {
+        self.ext_ack.as_ref()
     }
 
-    pub fn close(&self) -> io::Result<()> {
-        self.inner.close()
+    /// Whether an error or ACK message embeds the whole request or only
+    /// its header, `None` for other messages
+    pub fn error_layout(&self) -> Option<ErrorLayout> {
+        match self.payload {
+            Payload::Ack(ref req) | Payload::Err(_, ref req) => {
+                Some(ErrorLayout::detect(&self.header, req))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the messages packed in a buffer, such as a datagram or the
+/// output of `Socket::recv_dump_bytes`
+#[derive(Clone, Debug)]
+pub struct MsgIter<'a> {
+    bytes: &'a [u8],
+    config: SocketConfig,
+}
+
+impl<'a> MsgIter<'a> {
+    pub fn new(bytes: &'a [u8]) -> MsgIter<'a> {
+        MsgIter::with_config(bytes, &SocketConfig::new())
     }
 
-    pub fn send<'a>(&self, message: Msg<'a>, addr: &NetlinkAddr) -> io::Result<usize> {
-        let b = message.bytes()?;
-        self.inner.sendto(b.as_slice(), 0, &addr.as_sockaddr())
+    /// Iterates over messages padded to `config`'s alignment
+    pub fn with_config(bytes: &'a [u8], config: &SocketConfig) -> MsgIter<'a> {
+        MsgIter {
+            bytes,
+            config: *config,
+        }
     }
+}
 
-    pub fn send_multi<'a>(&self, messages: Vec<Msg<'a>>, addr: &NetlinkAddr) -> io::Result<usize> {
-        let mut bytes = vec![];
-        for m in messages {
-            let mut b = m.bytes()?;
-            bytes.append(&mut b);
+impl<'a> Iterator for MsgIter<'a> {
+    type Item = io::Result<Msg<'a>>;
+
+    fn next(&mut self) -> Option<io::Result<Msg<'a>>> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+
+        match Msg::from_bytes_with(self.bytes, &self.config) {
+            Ok((msg, _)) => {
+                let n = self
+                    .config
+                    .align(msg.header().msg_length() as usize)
+                    .min(self.bytes.len());
+                self.bytes = &self.bytes[n..];
+                Some(Ok(msg))
+            }
+            Err(e) => {
+                // stop after reporting a malformed message
+                self.bytes = &[];
+                Some(Err(e))
+            }
         }
+    }
+}
+
+/// Reads messages back out of a byte stream, such as netlink traffic piped
+/// through a Unix stream or a capture file, using each message's
+/// `nlmsg_len` to frame it. Messages are expected to be padded to the
+/// message alignment as in a datagram; the last one may be unpadded.
+#[derive(Debug)]
+pub struct FrameReader<R> {
+    reader: R,
+    done: bool,
+    config: SocketConfig,
+}
 
-        self.inner.sendto(bytes.as_slice(), 0, &addr.as_sockaddr())
+impl<R: Read> FrameReader<R> {
+    pub fn new(reader: R) -> FrameReader<R> {
+        FrameReader::with_config(reader, &SocketConfig::new())
     }
 
-    pub fn recv(&mut self) -> io::Result<(NetlinkAddr, Vec<Msg>)> {
-        let buffer = &mut self.buf[..];
-        let (saddr, _) = self.inner.recvfrom_into(buffer, 0)?;
-        let addr = sockaddr_to_netlinkaddr(&saddr)?;
-        let mut messages = vec![];
+    /// Reads messages padded to `config`'s alignment
+    pub fn with_config(reader: R, config: &SocketConfig) -> FrameReader<R> {
+        FrameReader {
+            reader,
+            done: false,
+            config: *config,
+        }
+    }
 
+    /// The message at the start of the stream, None at its end
+    fn read_frame(&mut self) -> io::Result<Option<OwnedMsg>> {
+        let mut len_bytes = [0u8; 4];
         let mut n = 0;
-        while let Ok((msg, num_bytes)) = Msg::from_bytes(&buffer[n..]) {
-            n += num_bytes;
-            let t = msg.header().msg_type();
-            match t {
-                MsgType::Done => break,
-                _ => {
-                    messages.push(msg);
+        while n < len_bytes.len() {
+            match self.reader.read(&mut len_bytes[n..]) {
+                Ok(0) if n == 0 => return Ok(None),
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "stream ends inside a message length",
+                    ))
                 }
+                Ok(read) => n += read,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
             }
         }
 
-        Ok((addr, messages))
+        let len = u32::from_ne_bytes(len_bytes) as usize;
+        if len < self.config.header_length() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "invalid message length",
+            ));
+        }
+        let mut bytes = vec![0u8; len];
+        bytes[..4].copy_from_slice(&len_bytes);
+        self.reader.read_exact(&mut bytes[4..])?;
+
+        // the padding of the last message may be missing
+        let padding = (self.config.align(len) - len) as u64;
+        io::copy(&mut (&mut self.reader).take(padding), &mut io::sink())?;
+
+        let (msg, _) = OwnedMsg::from_bytes_with(&bytes, &self.config)?;
+        Ok(Some(msg))
     }
 }
 
-// NLMSG_ALIGN()
-//       Round the length of a netlink message up to align it properly.
-// #define NLMSG_ALIGN(len) ( ((len)+NLMSG_ALIGNTO-1) & ~(NLMSG_ALIGNTO-1) )
-#[inline]
-fn nlmsg_align(len: usize) -> usize {
-    (len + (NLMSG_ALIGNTO - 1)) & !(NLMSG_ALIGNTO - 1)
-}
+impl<R: Read> Iterator for FrameReader<R> {
+    type Item = io::Result<OwnedMsg>;
 
-// #define NLMSG_HDRLEN     ((int) NLMSG_ALIGN(sizeof(struct nlmsghdr)))
-#[inline]
-fn nlmsg_header_length() -> usize {
-    nlmsg_align(size_of::<NlMsgHeader>())
+    fn next(&mut self) -> Option<io::Result<OwnedMsg>> {
+        if self.done {
+            return None;
+        }
+        match self.read_frame() {
+            Ok(Some(msg)) => Some(Ok(msg)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                // the stream cannot be framed past an error
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
 }
 
-// NLMSG_LENGTH()
-//        Given the payload length, len, this macro returns the aligned
-//        length to store in the nlmsg_len field of the nlmsghdr.
-// #define NLMSG_LENGTH(len) ((len)+NLMSG_ALIGN(NLMSG_HDRLEN))
-#[inline]
-fn nlmsg_length(len: usize) -> usize {
-    len + nlmsg_align(nlmsg_header_length())
+/// What a `Socket::recv_batch` call received
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct BatchStats {
+    datagrams: usize,
+    bytes: usize,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use byteorder::{NativeEndian, WriteBytesExt};
-    use std::io::Write;
-    use Protocol;
-
-    #[test]
-    fn test_send_recv() {
-        let send = Socket::new(Protocol::Usersock).unwrap();
-        let mut recv = Socket::new(Protocol::Usersock).unwrap();
-        let send_addr = NetlinkAddr::new(101, 0);
-        let recv_addr = NetlinkAddr::new(102, 0);
+impl BatchStats {
+    pub fn datagrams(&self) -> usize {
+        self.datagrams
+    }
 
-        send.bind(send_addr).unwrap();
-        recv.bind(recv_addr).unwrap();
+    /// Total length of the datagrams
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
 
-        let bytes = [0, 1, 2, 3, 4, 5];
-        let mut shdr = NlMsgHeader::request();
-        shdr.data_length(6).seq(1).pid(102);
-        let msg = Msg::new(shdr, Payload::Data(&bytes));
+/// A message that owns its bytes, for keeping messages past the next receive
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct OwnedMsg {
+    bytes: Vec<u8>,
+    config: SocketConfig,
+}
 
-        send.send(msg, &recv_addr).unwrap();
+impl OwnedMsg {
+    /// Copies the message at the start of `bytes`, returning it with the
+    /// number of bytes it occupies including trailing padding.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<(OwnedMsg, usize)> {
+        OwnedMsg::from_bytes_with(bytes, &SocketConfig::new())
+    }
 
-        let (ref addr, ref vec) = recv.recv().unwrap();
-        assert_eq!(vec.len(), 1);
+    /// Like `from_bytes`, for messages framed with `config`'s alignment
+    pub fn from_bytes_with(bytes: &[u8], config: &SocketConfig) -> io::Result<(OwnedMsg, usize)> {
+        let (msg, _) = Msg::from_bytes_with(bytes, config)?;
+        let len = msg.header().msg_length() as usize;
+        let owned = OwnedMsg {
+            bytes: bytes[..len].to_vec(),
+            config: *config,
+        };
+        Ok((owned, config.align(len).min(bytes.len())))
+    }
 
-        let ref msg = vec.first().unwrap();
-        assert_eq!(addr, &send_addr);
-        if let &Payload::Data(b) = msg.payload() {
-            assert_eq!(b, &bytes);
-        } else {
-            panic!("msg is not Data enum");
-        }
+    /// The message as received, borrowing from `self`
+    pub fn msg(&self) -> Msg<'_> {
+        // the bytes were validated by from_bytes
+        Msg::from_bytes_with(&self.bytes, &self.config).unwrap().0
     }
 
-    #[test]
-    fn test_send_multi_recv() {
-        let send = Socket::new(Protocol::Usersock).unwrap();
-        let mut recv = Socket::new(Protocol::Usersock).unwrap();
-        let send_addr = NetlinkAddr::new(99, 0);
-        let recv_addr = NetlinkAddr::new(100, 0);
+    pub fn header(&self) -> NlMsgHeader {
+        self.msg().header()
+    }
 
-        send.bind(send_addr).unwrap();
-        recv.bind(recv_addr).unwrap();
+    /// The message bytes exactly as received, without trailing padding
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
 
-        let bytes = [0, 1, 2, 3, 4, 5];
-        let mut shdr = NlMsgHeader::request();
-        shdr.data_length(6).multipart().seq(1).pid(100);
-        let msg = Msg::new(shdr, Payload::Data(&bytes));
-        let msg2 = msg.clone();
+/// Iterator over the replies of a dump, see `Socket::dump_iter`. An error
+/// reply is yielded as an `io::Error` and ends the iteration.
+pub struct DumpIter<'a> {
+    socket: &'a mut Socket,
+    pending: VecDeque<OwnedMsg>,
+    done: bool,
+}
 
-        let mut donehdr = NlMsgHeader::done();
-        donehdr.pid(100);
-        let donemsg = Msg::new(donehdr, Payload::None);
+impl<'a> Iterator for DumpIter<'a> {
+    type Item = io::Result<OwnedMsg>;
+
+    fn next(&mut self) -> Option<io::Result<OwnedMsg>> {
+        while !self.done {
+            let msg = match self.pending.pop_front() {
+                Some(msg) => msg,
+                None => {
+                    match self.socket.recv_owned() {
+                        Ok((_, messages)) => self.pending.extend(messages),
+                        Err(e) => {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let error = match *msg.msg().payload() {
+                Payload::Data(_) => None,
+                // Done, or the ACK of an NLM_F_ACK request
+                Payload::None | Payload::Ack(_) | Payload::Done(0) => {
+                    self.done = true;
+                    return None;
+                }
+                Payload::Err(errno, _) | Payload::Done(errno) => {
+                    Some(io::Error::from_raw_os_error(-errno))
+                }
+            };
+            if let Some(e) = error {
+                self.done = true;
+                return Some(Err(e));
+            }
+            return Some(Ok(msg));
+        }
+        None
+    }
+}
 
-        send.send_multi(vec![msg, msg2, donemsg], &recv_addr)
-            .unwrap();
+// #[repr(C)]
+// #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+// struct NlErr {
+//     /// 0 if used as acknowledgement
+//     err: u32,
+//     /// Msg header that caused the error
+//     hdr: NlMsgHeader,
+// }
 
-        let (ref addr, ref vec) = recv.recv().unwrap();
-        assert_eq!(vec.len(), 2);
+/// One synchronous exchange on a socket, see `Socket::begin_request`. It
+/// borrows the socket mutably, so no other request can start before it is
+/// dropped.
+pub struct RequestGuard<'a> {
+    socket: &'a mut Socket,
+    seq: u32,
+}
 
-        let ref msg = vec.first().unwrap();
-        assert_eq!(addr, &send_addr);
-        if let &Payload::Data(b) = msg.payload() {
-            assert_eq!(b, &bytes);
-        } else {
-            panic!("msg is not Data enum");
-        }
+impl<'a> RequestGuard<'a> {
+    /// Sequence number of the exchange, set on every message sent
+    pub fn seq(&self) -> u32 {
+        self.seq
     }
 
-    #[test]
-    fn test_payload_decode() {
-        let bytes = [0, 1, 2, 3, 4, 5];
-        let (payload, n) = Payload::data(&bytes, bytes.len()).unwrap();
-        assert_eq!(n, bytes.len());
+    /// Sends `message` with the exchange's sequence number
+    pub fn send(&mut self, message: Msg<'_>, addr: &NetlinkAddr) -> io::Result<usize> {
+        let mut header = message.header();
+        header.seq(self.seq);
+        self.socket
+            .send(Msg::new(header, message.payload().clone()), addr)
+    }
 
-        if let Payload::Data(b) = payload {
-            assert_eq!(b, &bytes);
-        } else {
-            panic!("payload is not Data enum");
+    /// Receives until a datagram holds replies to the exchange and returns
+    /// those, dropping messages with other sequence numbers.
+    pub fn recv(&mut self) -> io::Result<Vec<OwnedMsg>> {
+        loop {
+            let (_, messages) = self.socket.recv_owned()?;
+            let seq = self.seq;
+            let replies: Vec<OwnedMsg> = messages
+                .into_iter()
+                .filter(|m| m.header().msg_seq() == seq)
+                .collect();
+            if !replies.is_empty() {
+                return Ok(replies);
+            }
         }
     }
+}
 
-    #[test]
-    fn test_payload_decode_with_err() {
-        let mut bytes = vec![];
-        bytes.write_u32::<NativeEndian>(1).unwrap();
+/// Which way a traced message is travelling
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Send,
+    Recv,
+}
 
-        // Little endian only right now
-        let expected = [20, 0, 0, 0, 0, 0, 1, 3, 1, 0, 0, 0, 9, 0, 0, 0];
-        let mut hdr = NlMsgHeader::request();
-        hdr.data_length(4).pid(9).seq(1).dump();
+/// How a request ended, see `Socket::recv_control`
+#[derive(Debug)]
+pub enum ControlOutcome {
+    Ack,
+    /// An error reply, or a `Done` whose status reports an error, with
+    /// the errno the kernel reported
+    Error(io::Error),
+    /// The `Done` message ending a complete dump
+    Done,
+}
 
-        #[allow(clippy::unused_io_amount)]
-        let _ = bytes.write(&expected).unwrap();
+/// Callback installed with `Socket::set_trace`
+pub type Trace = Box<dyn Fn(Direction, &Msg) + Send>;
 
-        let (p, n) = Payload::nlmsg_error(&bytes).unwrap();
+/// Callback for one message type, see `Socket::dispatch`
+pub type Handler = Box<dyn FnMut(&Msg)>;
 
-        assert_eq!(n, bytes.len());
-        if let Payload::Err(_, h) = p {
-            assert_eq!(h, hdr);
-        } else {
-            panic!("payload is not Err enum");
-        }
-    }
+/// Callback installed with `Socket::on_overrun`
+pub type OverrunHandler = Box<dyn FnMut(&mut Socket) + Send>;
 
-    #[test]
-    fn test_payload_decode_with_ack() {
-        let mut bytes = vec![];
-        bytes.write_u32::<NativeEndian>(0).unwrap();
+pub struct Socket {
+    inner: SocketImpl,
+    protocol: i32,
+    buf: Vec<u8>,
+    trace: Option<Trace>,
+    overrun: Option<OverrunHandler>,
+    max_send_size: usize,
+    bound: Cell<bool>,
+    next_seq: u32,
+    last_sent_seq: Cell<u32>,
+    // sender and range of buf recv_one has not returned yet
+    unread: Option<(NetlinkAddr, usize, usize)>,
+    rx_ring: Option<RxRing>,
+    config: SocketConfig,
+}
 
-        let mut hdr = NlMsgHeader::request();
-        hdr.data_length(4).pid(9).seq(1).dump();
+impl Socket {
+    /// Opens a blocking socket for `protocol`. The descriptor is created
+    /// with `SOCK_CLOEXEC`, so it does not leak into programs started with
+    /// exec; use `set_cloexec(false)` to hand it to a child.
+    pub fn new<P: Into<i32>>(protocol: P) -> io::Result<Socket> {
+        Socket::new_with_flags(protocol, true, false)
+    }
 
-        let _ = bytes.write(hdr.bytes()).unwrap();
+    /// Opens a socket like `new` that frames the messages it sends and
+    /// receives with `config`
+    pub fn with_config<P: Into<i32>>(protocol: P, config: &SocketConfig) -> io::Result<Socket> {
+        let mut socket = Socket::new(protocol)?;
+        socket.config = *config;
+        Ok(socket)
+    }
 
-        let (p, n) = Payload::nlmsg_error(&bytes).unwrap();
+    /// The framing the socket uses, `SocketConfig::new()` unless opened
+    /// with `with_config`
+    pub fn config(&self) -> &SocketConfig {
+        &self.config
+    }
 
-        assert_eq!(n, bytes.len());
-        if let Payload::Ack(h) = p {
-            assert_eq!(h, hdr);
-        } else {
+    /// Opens a socket like `new`, returning `None` where the environment
+    /// offers no netlink, e.g. sandboxes refusing `AF_NETLINK` with EPERM or
+    /// kernels built without the protocol.
+    pub fn try_new<P: Into<i32>>(protocol: P) -> io::Result<Option<Socket>> {
+        match Socket::new(protocol) {
+            Ok(socket) => Ok(Some(socket)),
+            Err(ref e) if is_unavailable(e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens a socket with `SOCK_CLOEXEC` and `SOCK_NONBLOCK` set as
+    /// requested. Both are applied by socket(2) itself, so a fork on another
+    /// thread cannot observe the descriptor without them.
+    pub fn new_with_flags<P: Into<i32>>(
+        protocol: P,
+        cloexec: bool,
+        nonblocking: bool,
+    ) -> io::Result<Socket> {
+        let mut socket_type = SOCK_RAW;
+        if cloexec {
+            socket_type |= SOCK_CLOEXEC;
+        }
+        if nonblocking {
+            socket_type |= SOCK_NONBLOCK;
+        }
+        let protocol = protocol.into();
+        let s = SocketImpl::new(AF_NETLINK, socket_type, protocol)?;
+        Socket::from_impl(s, protocol, false)
+    }
+
+    /// Adopts a netlink socket created elsewhere. The protocol is read from
+    /// the descriptor, and a descriptor of another family is refused with
+    /// `InvalidInput` and closed.
+    pub fn from_owned_fd(fd: OwnedFd) -> io::Result<Socket> {
+        let s = SocketImpl::from_fd(fd.into_raw_fd());
+        if s.getsockopt::<c_int>(SOL_SOCKET, SO_DOMAIN)? != AF_NETLINK {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "not a netlink socket",
+            ));
+        }
+        let protocol = s.getsockopt::<c_int>(SOL_SOCKET, SO_PROTOCOL)?;
+        let bound = sockaddr_to_netlinkaddr(&s.getsockname()?)?.pid() != 0;
+        Socket::from_impl(s, protocol, bound)
+    }
+
+    fn from_impl(s: SocketImpl, protocol: i32, bound: bool) -> io::Result<Socket> {
+        let buf = vec![0u8; 4096];
+        let max_send_size = s.getsockopt::<c_int>(SOL_SOCKET, SO_SNDBUF)? as usize;
+        let mut socket = Socket {
+            inner: s,
+            protocol,
+            buf,
+            trace: None,
+            overrun: None,
+            max_send_size,
+            bound: Cell::new(bound),
+            next_seq: 1,
+            last_sent_seq: Cell::new(0),
+            unread: None,
+            rx_ring: None,
+            config: SocketConfig::new(),
+        };
+        // the acknowledgement of a request echoes it, so a request must also
+        // fit the receive buffer
+        socket.max_send_size = cmp::min(max_send_size, socket.max_msg_size()?);
+        Ok(socket)
+    }
+
+    /// Creates a socket in the network namespace `netns_fd` refers to, e.g.
+    /// an open `/proc/<pid>/ns/net` or `/run/netns/<name>`. The calling
+    /// thread joins the namespace for the `socket` call only and is moved
+    /// back afterwards, also when creating the socket fails. Needs
+    /// `CAP_SYS_ADMIN` in both namespaces. If moving back fails, that error
+    /// is returned; when the socket call failed as well the process aborts
+    /// rather than run on in the other namespace.
+    pub fn new_in_netns<P: Into<i32>>(protocol: P, netns_fd: RawFd) -> io::Result<Socket> {
+        let guard = NetnsGuard::enter(netns_fd)?;
+        let socket = Socket::new(protocol)?;
+        guard.restore()?;
+        Ok(socket)
+    }
+
+    /// Protocol number the socket was opened with, see `Protocol::from_i32`
+    pub fn protocol(&self) -> i32 {
+        self.protocol
+    }
+
+    /// Size of the buffer datagrams are received into; longer datagrams
+    /// are truncated.
+    pub fn buffer_capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Grows the receive buffer by `additional` bytes
+    pub fn reserve_buffer(&mut self, additional: usize) {
+        let len = self.buf.len() + additional;
+        self.buf.resize(len, 0);
+    }
+
+    /// Largest datagram `send` and `send_multi` will pass to the kernel,
+    /// initially the smaller of the socket's `SO_SNDBUF` value and
+    /// `max_msg_size`.
+    pub fn max_send_size(&self) -> usize {
+        self.max_send_size
+    }
+
+    /// Sets the limit checked by `send` and `send_multi`. Attributes that
+    /// do not fit must be split across several messages, as a dump does.
+    pub fn set_max_send_size(&mut self, size: usize) {
+        self.max_send_size = size;
+    }
+
+    /// Bytes queued for sending but not yet sent (`SIOCOUTQ`). Netlink hands
+    /// each datagram to its receivers within the send call, so nothing is
+    /// ever left queued; current kernels do not implement the ioctl for
+    /// netlink and fail it with ENOTTY, which is reported as 0.
+    pub fn send_queue_len(&self) -> io::Result<usize> {
+        // SIOCOUTQ shares its number with TIOCOUTQ
+        match self.inner.ioctl_int(TIOCOUTQ) {
+            Ok(len) => Ok(len as usize),
+            Err(ref e) if e.raw_os_error() == Some(ENOTTY) => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn check_send_size(&self, len: usize) -> io::Result<()> {
+        if len > self.max_send_size {
+            Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "message of {} bytes exceeds the maximum send size of {} bytes",
+                    len, self.max_send_size
+                ),
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Installs a callback invoked with every message sent or received
+    /// through this socket, for debugging protocol exchanges.
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Removes the callback installed by `set_trace`
+    pub fn clear_trace(&mut self) {
+        self.trace = None;
+    }
+
+    #[inline]
+    fn trace(&self, direction: Direction, message: &Msg) {
+        if let Some(ref trace) = self.trace {
+            trace(direction, message);
+        }
+    }
+
+    /// Installs a callback for when the kernel dropped messages because the
+    /// receive buffer was full (ENOBUFS). The receive calls then drain the
+    /// socket, run the callback, which would typically request a full dump
+    /// to resynchronize, and go on waiting for the next datagram. Without a
+    /// callback the overrun is returned as an error.
+    pub fn on_overrun(&mut self, handler: OverrunHandler) {
+        self.overrun = Some(handler);
+    }
+
+    pub fn bind(&self, addr: NetlinkAddr) -> io::Result<()> {
+        self.inner.bind(&addr.as_sockaddr())?;
+        self.bound.set(true);
+        Ok(())
+    }
+
+    /// Whether the socket has a local address. Besides `bind`, the first
+    /// successful send binds it, since the kernel then assigns a pid.
+    pub fn is_bound(&self) -> bool {
+        self.bound.get()
+    }
+
+    /// Sets or clears the close-on-exec flag of the descriptor
+    pub fn set_cloexec(&self, on: bool) -> io::Result<()> {
+        self.inner.set_cloexec(on)
+    }
+
+    /// Sets the priority (`SO_PRIORITY`) of the packets the socket sends.
+    /// Values outside 0 to 6 need `CAP_NET_ADMIN`.
+    pub fn set_priority(&self, priority: i32) -> io::Result<()> {
+        self.inner
+            .setsockopt(SOL_SOCKET, SO_PRIORITY, priority as c_int)
+    }
+
+    /// The socket's `SO_PRIORITY`
+    pub fn priority(&self) -> io::Result<i32> {
+        self.inner.getsockopt::<c_int>(SOL_SOCKET, SO_PRIORITY)
+    }
+
+    /// Sets `SO_REUSEADDR`. Netlink pids are not tied to a port, so this
+    /// neither lets two sockets bind the same pid nor shortens any wait
+    /// before rebinding; it only satisfies code that sets it on every
+    /// socket.
+    pub fn set_reuse_addr(&self, on: bool) -> io::Result<()> {
+        self.inner.setsockopt(SOL_SOCKET, SO_REUSEADDR, on as c_int)
+    }
+
+    /// Whether `SO_REUSEADDR` is set
+    pub fn reuse_addr(&self) -> io::Result<bool> {
+        self.inner
+            .getsockopt::<c_int>(SOL_SOCKET, SO_REUSEADDR)
+            .map(|on| on != 0)
+    }
+
+    /// Address the socket is bound to, with the pid the kernel assigned
+    pub fn local_addr(&self) -> io::Result<NetlinkAddr> {
+        sockaddr_to_netlinkaddr(&self.inner.getsockname()?)
+    }
+
+    /// Joins multicast `group`, which unlike the bind mask is not limited
+    /// to groups 1 to 32. Errors name the group and keep the kind of the
+    /// setsockopt error, which itself is kept in a `MembershipError`.
+    pub fn add_membership(&self, group: u32) -> io::Result<()> {
+        self.inner
+            .setsockopt(SOL_NETLINK, NETLINK_ADD_MEMBERSHIP, group)
+            .map_err(|e| membership_error("join", group, e))
+    }
+
+    pub fn drop_membership(&self, group: u32) -> io::Result<()> {
+        self.inner
+            .setsockopt(SOL_NETLINK, NETLINK_DROP_MEMBERSHIP, group)
+            .map_err(|e| membership_error("leave", group, e))
+    }
+
+    /// Multicast groups the socket is joined to, in ascending order
+    pub fn memberships(&self) -> io::Result<Vec<u32>> {
+        // the first call only reports the size of the group bitmap
+        let len = self
+            .inner
+            .getsockopt_into(SOL_NETLINK, NETLINK_LIST_MEMBERSHIPS, &mut [])?;
+        let mut bitmap = vec![0u8; len];
+        let len = self
+            .inner
+            .getsockopt_into(SOL_NETLINK, NETLINK_LIST_MEMBERSHIPS, &mut bitmap)?;
+        bitmap.truncate(len);
+
+        // an array of u32 in host order, bit n of word w is group 32w + n + 1
+        let mut groups = vec![];
+        let mut cursor = Cursor::new(&bitmap[..]);
+        let mut base = 1;
+        while let Ok(word) = cursor.read_u32::<NativeEndian>() {
+            for bit in 0..32 {
+                if word & (1 << bit) != 0 {
+                    groups.push(base + bit);
+                }
+            }
+            base += 32;
+        }
+        Ok(groups)
+    }
+
+    /// Binds to a pid the kernel picks, which cannot collide with ports in
+    /// use, and returns the bound address.
+    pub fn bind_auto(&self) -> io::Result<NetlinkAddr> {
+        self.bind(NetlinkAddr::new(0, 0))?;
+        self.local_addr()
+    }
+
+    /// Binds with a kernel assigned pid and joins each of `groups`,
+    /// returning the bound address.
+    pub fn subscribe(&self, groups: &[u32]) -> io::Result<NetlinkAddr> {
+        self.bind_auto()?;
+        for &group in groups {
+            self.add_membership(group)?;
+        }
+        self.local_addr()
+    }
+
+    /// Closes the socket. An interrupted close still releases the
+    /// descriptor and counts as success; closing again returns EBADF.
+    /// Sending or receiving afterwards fails with `NotConnected`.
+    pub fn close(&self) -> io::Result<()> {
+        self.inner.close()
+    }
+
+    pub fn send<'a>(&self, message: Msg<'a>, addr: &NetlinkAddr) -> io::Result<usize> {
+        self.trace(Direction::Send, &message);
+        let b = message.bytes()?;
+        let sent = self.send_bytes(&b, addr)?;
+        self.last_sent_seq.set(message.header().msg_seq());
+        Ok(sent)
+    }
+
+    /// Size of the datagram `send_multi` would build from `messages`, or
+    /// the error it would fail with for exceeding `max_send_size`, so a
+    /// caller can split a batch before sending it
+    pub fn validate_batch(&self, messages: &[Msg]) -> io::Result<usize> {
+        let total = messages.iter().map(|m| self.config.wire_len(m)).sum();
+        self.check_send_size(total)?;
+        Ok(total)
+    }
+
+    pub fn send_multi<'a>(&self, messages: Vec<Msg<'a>>, addr: &NetlinkAddr) -> io::Result<usize> {
+        let mut encoded = vec![];
+        let mut seq = None;
+        for m in messages {
+            self.trace(Direction::Send, &m);
+            encoded.push(m.bytes()?);
+            seq = Some(m.header().msg_seq());
+        }
+
+        let parts: Vec<&[u8]> = encoded.iter().map(|b| &b[..]).collect();
+        let sent = self.send_bytes(&self.config.concat(&parts), addr)?;
+        if let Some(seq) = seq {
+            self.last_sent_seq.set(seq);
+        }
+        Ok(sent)
+    }
+
+    /// Sequence number of the last message sent with `send` or
+    /// `send_multi`, 0 before the first. Raw bytes sent with `send_bytes`
+    /// or `send_vectored` are not looked at.
+    pub fn last_sent_seq(&self) -> u32 {
+        self.last_sent_seq.get()
+    }
+
+    /// Sends the concatenation of `bufs` as one datagram without copying
+    /// them together first. The bytes are not traced, as they need not
+    /// split into messages along the slices.
+    pub fn send_vectored(&self, bufs: &[IoSlice], addr: &NetlinkAddr) -> io::Result<usize> {
+        self.check_send_size(bufs.iter().map(|b| b.len()).sum())?;
+        let sent = self.inner.sendmsg_vectored(bufs, 0, &addr.as_sockaddr())?;
+        self.bound.set(true);
+        Ok(sent)
+    }
+
+    /// Receives one datagram scattered over `bufs`, returning the sender and
+    /// the number of bytes received. What does not fit is discarded.
+    pub fn recv_vectored(&self, bufs: &mut [IoSliceMut]) -> io::Result<(NetlinkAddr, usize)> {
+        let (sa, len) = self.inner.recvmsg_vectored(bufs, 0)?;
+        Ok((sockaddr_to_netlinkaddr(&sa)?, len))
+    }
+
+    /// Sends `bytes` as one datagram as they are, e.g. replayed or fuzzed
+    /// traffic. The caller is responsible for framing: message lengths,
+    /// 4 byte alignment between messages. Nothing is traced.
+    pub fn send_bytes(&self, bytes: &[u8], addr: &NetlinkAddr) -> io::Result<usize> {
+        self.check_send_size(bytes.len())?;
+        let sent = self.inner.sendto(bytes, 0, &addr.as_sockaddr())?;
+        self.bound.set(true);
+        Ok(sent)
+    }
+
+    /// Receives a datagram and returns its messages up to `Done`. A message
+    /// that fails to parse is returned as an `InvalidData` error and an
+    /// `NLMSG_OVERRUN` as ENOBUFS, see `recv_checked` to keep the messages
+    /// before them.
+    pub fn recv(&mut self) -> io::Result<(NetlinkAddr, Vec<Msg<'_>>)> {
+        let (addr, len) = self.recv_datagram()?;
+        Ok((
+            addr,
+            parse_datagram(&self.buf[..len], &self.trace, &self.config)?,
+        ))
+    }
+
+    /// Like `recv` for a client that only talks to the kernel: a datagram
+    /// from another process (pid not 0), which could be spoofing replies,
+    /// is discarded and reported as `PermissionDenied`.
+    pub fn recv_from_kernel(&mut self) -> io::Result<Vec<Msg<'_>>> {
+        let (addr, len) = self.recv_datagram()?;
+        if addr.pid() != 0 {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("message from pid {} rather than the kernel", addr.pid()),
+            ));
+        }
+        parse_datagram(&self.buf[..len], &self.trace, &self.config)
+    }
+
+    /// Receives multicast messages from every network namespace that has
+    /// an id assigned in the socket's own; see `recv_with_nsid`. Needs
+    /// `CAP_NET_BROADCAST`.
+    pub fn set_listen_all_nsid(&self, on: bool) -> io::Result<()> {
+        self.inner
+            .setsockopt(SOL_NETLINK, NETLINK_LISTEN_ALL_NSID, on as c_int)
+    }
+
+    /// Like `recv`, also returning the id of the network namespace the
+    /// datagram came from, as assigned in the socket's own namespace. -1
+    /// stands for the local namespace, or for any message when
+    /// `set_listen_all_nsid` is off.
+    pub fn recv_with_nsid(&mut self) -> io::Result<(NetlinkAddr, i32, Vec<Msg<'_>>)> {
+        let (addr, len, nsid) =
+            self.recv_datagram_cmsg(Some((SOL_NETLINK, NETLINK_LISTEN_ALL_NSID)))?;
+        let messages = parse_datagram(&self.buf[..len], &self.trace, &self.config)?;
+        Ok((addr, nsid.unwrap_or(-1), messages))
+    }
+
+    /// Like `recv`, but with a result per message, so a message that fails
+    /// to parse shows up as an error after the messages before it. Nothing
+    /// after a malformed message can be framed, so it is always the last.
+    pub fn recv_checked(&mut self) -> io::Result<(NetlinkAddr, Vec<io::Result<Msg<'_>>>)> {
+        let (addr, len) = self.recv_datagram()?;
+        Ok((
+            addr,
+            parse_datagram_checked(&self.buf[..len], &self.trace, &self.config),
+        ))
+    }
+
+    // Receives one datagram into buf, handling overruns as set up with
+    // on_overrun
+    fn recv_datagram(&mut self) -> io::Result<(NetlinkAddr, usize)> {
+        let (addr, len, _) = self.recv_datagram_cmsg(None)?;
+        Ok((addr, len))
+    }
+
+    // Like recv_datagram, also returning the int control message of the
+    // given level and type if one is asked for and was sent along
+    fn recv_datagram_cmsg(
+        &mut self,
+        cmsg: Option<(c_int, c_int)>,
+    ) -> io::Result<(NetlinkAddr, usize, Option<c_int>)> {
+        self.unread = None;
+        loop {
+            let result = match cmsg {
+                Some((level, ty)) => self.inner.recvmsg_cmsg_int(&mut self.buf[..], 0, level, ty),
+                None => self
+                    .inner
+                    .recvfrom_into(&mut self.buf[..], 0)
+                    .map(|(saddr, len)| (saddr, len, None)),
+            };
+            match result {
+                Err(ref e) if self.handles_overrun(e) => self.run_overrun_handler()?,
+                result => {
+                    let (saddr, len, value) = result?;
+                    return Ok((sockaddr_to_netlinkaddr(&saddr)?, len, value));
+                }
+            }
+        }
+    }
+
+    // Whether `e` is an overrun the `on_overrun` handler takes care of
+    fn handles_overrun(&self, e: &io::Error) -> bool {
+        e.raw_os_error() == Some(ENOBUFS) && self.overrun.is_some()
+    }
+
+    // Drains the socket and runs the `on_overrun` handler
+    fn run_overrun_handler(&mut self) -> io::Result<()> {
+        self.drain()?;
+        if let Some(mut handler) = self.overrun.take() {
+            handler(self);
+            // unless the handler installed a new one
+            if self.overrun.is_none() {
+                self.overrun = Some(handler);
+            }
+        }
+        Ok(())
+    }
+
+    // Discards the queued datagrams
+    fn drain(&mut self) -> io::Result<()> {
+        loop {
+            match self.inner.recvfrom_into(&mut self.buf[..], MSG_DONTWAIT) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => {}
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.raw_os_error() == Some(ENOBUFS) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Returns the next message of the current datagram, receiving another
+    /// datagram once it is used up. A `Done` message is returned as `None`
+    /// and, as with `recv`, drops whatever follows it in the datagram. An
+    /// NLMSG_OVERRUN fails with ENOBUFS, also as with `recv`.
+    ///
+    /// Any other receive call discards what is left of the current datagram.
+    pub fn recv_one(&mut self) -> io::Result<(NetlinkAddr, Option<Msg<'_>>)> {
+        let (addr, start, end) = loop {
+            let (addr, start, end) = match self.unread.take() {
+                Some(unread) => unread,
+                None => {
+                    let (addr, len) = self.recv_datagram()?;
+                    (addr, 0, len)
+                }
+            };
+
+            let (hdr, _) = NlMsgHeader::from_bytes(&self.buf[start..end])?;
+            let next = (start + self.config.align(hdr.msg_length() as usize)).min(end);
+            match hdr.msg_type() {
+                MsgType::Overrun => return Err(io::Error::from_raw_os_error(ENOBUFS)),
+                // whatever follows is dropped, leaving unread empty
+                MsgType::Done => break (addr, start, end),
+                _ => {}
+            }
+            if next < end {
+                self.unread = Some((addr, next, end));
+            }
+            if hdr.msg_type() != MsgType::Noop {
+                break (addr, start, end);
+            }
+        };
+
+        let (msg, _) = Msg::from_bytes_with(&self.buf[start..end], &self.config)?;
+        self.trace(Direction::Recv, &msg);
+        match msg.header().msg_type() {
+            MsgType::Done => Ok((addr, None)),
+            _ => Ok((addr, Some(msg))),
+        }
+    }
+
+    /// Like `recv`, also returning a copy of the datagram exactly as the
+    /// kernel delivered it.
+    pub fn recv_raw(&mut self) -> io::Result<(NetlinkAddr, Vec<u8>, Vec<Msg<'_>>)> {
+        let (addr, len) = self.recv_datagram()?;
+        let raw = self.buf[..len].to_vec();
+        Ok((
+            addr,
+            raw,
+            parse_datagram(&self.buf[..len], &self.trace, &self.config)?,
+        ))
+    }
+
+    /// Keeps receiving until `deadline`, returning every datagram that
+    /// arrived before it. Messages after a `Done` in a datagram are
+    /// dropped, as with `recv`. A signal interrupting the wait does not end
+    /// it early. On a receive or parse error the error is returned and the
+    /// datagrams collected so far are dropped.
+    pub fn recv_until(
+        &mut self,
+        deadline: Instant,
+    ) -> io::Result<Vec<(NetlinkAddr, Vec<OwnedMsg>)>> {
+        let mut datagrams = vec![];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(datagrams);
+            }
+            match self.inner.poll_in(deadline - now) {
+                Ok(true) => {}
+                Ok(false) => return Ok(datagrams),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+
+            let (addr, mut messages) = self.recv_owned()?;
+            if let Some(i) = messages
+                .iter()
+                .position(|m| m.header().msg_type() == MsgType::Done)
+            {
+                messages.truncate(i);
+            }
+            datagrams.push((addr, messages));
+        }
+    }
+
+    /// Waits for a datagram, then takes up to `max_datagrams - 1` more that
+    /// are already queued, and returns the messages of all of them with the
+    /// number of datagrams and bytes received.
+    pub fn recv_batch(&mut self, max_datagrams: usize) -> io::Result<(Vec<OwnedMsg>, BatchStats)> {
+        let mut messages = vec![];
+        let mut stats = BatchStats::default();
+        while stats.datagrams < max_datagrams {
+            if stats.datagrams > 0 && !self.inner.poll_in(Duration::from_secs(0))? {
+                break;
+            }
+            let (_, len) = self.recv_datagram()?;
+            messages.extend(copy_datagram(&self.buf[..len], &self.trace, &self.config)?);
+            stats.datagrams += 1;
+            stats.bytes += len;
+        }
+        Ok((messages, stats))
+    }
+
+    /// Moves the socket into a thread that receives in a loop and forwards
+    /// each message to the returned channel. An overrun (ENOBUFS) is
+    /// forwarded and receiving goes on; any other error is forwarded and
+    /// ends the thread. Dropping the receiver stops the thread once the
+    /// next datagram arrives, as it blocks receiving until then.
+    pub fn into_event_stream(mut self) -> (JoinHandle<()>, Receiver<io::Result<OwnedMsg>>) {
+        let (tx, rx) = mpsc::channel();
+        let handle = thread::spawn(move || loop {
+            match self.recv_owned() {
+                Ok((_, messages)) => {
+                    for msg in messages {
+                        if tx.send(Ok(msg)).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let overrun = e.raw_os_error() == Some(ENOBUFS);
+                    if tx.send(Err(e)).is_err() || !overrun {
+                        return;
+                    }
+                }
+            }
+        });
+        (handle, rx)
+    }
+
+    // Receives one datagram and copies out all of its messages but NOOPs
+    fn recv_owned(&mut self) -> io::Result<(NetlinkAddr, Vec<OwnedMsg>)> {
+        let (addr, len) = self.recv_datagram()?;
+        let messages = copy_datagram(&self.buf[..len], &self.trace, &self.config)?;
+        Ok((addr, messages))
+    }
+
+    /// Maps a receive ring of at least `frame_nr` frames of `frame_size`
+    /// bytes, which the kernel fills without a syscall per message; read
+    /// it with `recv_ring`. Messages larger than a frame still go through
+    /// the socket. Netlink rings were removed in Linux 4.7, later kernels
+    /// fail with `ErrorKind::Unsupported`.
+    pub fn enable_rx_ring(&mut self, frame_size: usize, frame_nr: usize) -> io::Result<()> {
+        self.rx_ring = Some(RxRing::new(&self.inner, frame_size, frame_nr)?);
+        Ok(())
+    }
+
+    /// Whether the kernel supports a memory mapped transmit ring. The
+    /// netlink rings were removed in Linux 4.7, and this crate sends
+    /// through `sendmsg` either way; the probe lets callers branch.
+    pub fn tx_ring_supported(&self) -> bool {
+        tx_ring_supported(&self.inner)
+    }
+
+    /// Takes the next datagram from the ring set up by `enable_rx_ring`,
+    /// waiting for one if the ring is empty.
+    pub fn recv_ring(&mut self) -> io::Result<(NetlinkAddr, Vec<OwnedMsg>)> {
+        loop {
+            let ring = self.rx_ring.as_mut().ok_or_else(|| {
+                io::Error::new(ErrorKind::InvalidInput, "no receive ring is enabled")
+            })?;
+            match ring.status() {
+                NL_MMAP_STATUS_VALID => {
+                    let (addr, data) = ring.data();
+                    let messages = copy_datagram(data, &self.trace, &self.config);
+                    ring.release();
+                    return Ok((addr, messages?));
+                }
+                // did not fit a frame and was queued to the socket instead
+                NL_MMAP_STATUS_COPY => {
+                    ring.release();
+                    return self.recv_owned();
+                }
+                NL_MMAP_STATUS_SKIP => ring.release(),
+                _ => self.inner.wait_in()?,
+            }
+        }
+    }
+
+    /// Receives a datagram of any size into a buffer allocated to fit it,
+    /// leaving the socket's own buffer alone. Costs a second syscall to peek
+    /// at the datagram's length first.
+    pub fn recv_exact_sized(&mut self) -> io::Result<(NetlinkAddr, Vec<OwnedMsg>)> {
+        self.unread = None;
+        loop {
+            // with MSG_TRUNC the full length is returned even for an empty buffer
+            let len = match self.inner.recvfrom_into(&mut [], MSG_PEEK | MSG_TRUNC) {
+                Err(ref e) if self.handles_overrun(e) => {
+                    self.run_overrun_handler()?;
+                    continue;
+                }
+                result => result?.1,
+            };
+            let mut datagram = vec![0u8; len];
+            let (sa, len) = match self.inner.recvfrom_into(&mut datagram, 0) {
+                Err(ref e) if self.handles_overrun(e) => {
+                    self.run_overrun_handler()?;
+                    continue;
+                }
+                result => result?,
+            };
+            let messages = copy_datagram(&datagram[..len], &self.trace, &self.config)?;
+            return Ok((sockaddr_to_netlinkaddr(&sa)?, messages));
+        }
+    }
+
+    /// Sends the dump `request` and returns an iterator that receives its
+    /// replies as they are consumed, ending at the `Done` message.
+    pub fn dump_iter<'a>(
+        &'a mut self,
+        request: Msg<'_>,
+        addr: &NetlinkAddr,
+    ) -> io::Result<DumpIter<'a>> {
+        self.send(request, addr)?;
+        Ok(DumpIter {
+            socket: self,
+            pending: VecDeque::new(),
+            done: false,
+        })
+    }
+
+    /// Receives the datagrams of a multipart dump up to its `Done` message
+    /// and returns the messages before it in one buffer, each padded to the
+    /// message alignment so the buffer can be walked with `MsgIter`.
+    ///
+    /// An error reply ends the dump and is returned as an `io::Error`, an
+    /// NLMSG_OVERRUN as ENOBUFS.
+    pub fn recv_dump_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let mut dump = vec![];
+        loop {
+            let (_, len) = self.recv_datagram()?;
+            let datagram = &self.buf[..len];
+
+            let mut n = 0;
+            while n < len {
+                let (msg, _) = Msg::from_bytes_with(&datagram[n..], &self.config)?;
+                self.trace(Direction::Recv, &msg);
+                let msg_len = msg.header().msg_length() as usize;
+                match msg.header().msg_type() {
+                    MsgType::Noop => {
+                        n += self.config.align(msg_len);
+                        continue;
+                    }
+                    // as in copy_datagram
+                    MsgType::Overrun => return Err(io::Error::from_raw_os_error(ENOBUFS)),
+                    _ => {}
+                }
+                match *msg.payload() {
+                    Payload::Data(_) => {}
+                    // Done, or the ACK of an NLM_F_ACK request
+                    Payload::None | Payload::Ack(_) | Payload::Done(0) => return Ok(dump),
+                    // a Done with an error status ends an interrupted dump
+                    Payload::Err(errno, _) | Payload::Done(errno) => {
+                        return Err(io::Error::from_raw_os_error(-errno))
+                    }
+                }
+
+                dump.extend_from_slice(&datagram[n..n + msg_len]);
+                let padded = self.config.align(dump.len());
+                dump.resize(padded, 0);
+                n += self.config.align(msg_len);
+            }
+        }
+    }
+
+    /// Receives a datagram and passes each message to the handler for its
+    /// type. Messages without a handler are dropped.
+    pub fn dispatch(&mut self, handlers: &mut HashMap<MsgType, Handler>) -> io::Result<()> {
+        let (_, messages) = self.recv()?;
+        for msg in &messages {
+            if let Some(handler) = handlers.get_mut(&msg.header().msg_type()) {
+                handler(msg);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends `message` and returns the next datagram received, without
+    /// checking that it is the reply to `message`.
+    pub fn send_and_recv<'a>(
+        &mut self,
+        message: Msg<'a>,
+        addr: &NetlinkAddr,
+    ) -> io::Result<(NetlinkAddr, Vec<Msg<'_>>)> {
+        self.send(message, addr)?;
+        self.recv()
+    }
+
+    /// Sends `message` with an ACK requested and collects the replies that
+    /// carry its sequence number until the ACK arrives. With
+    /// `NlMsgHeader::echo` set, the kernel answers a change with both the
+    /// resulting object and the ACK, so the echoed objects are returned.
+    /// A dump is answered with a `Done` instead of the ACK, which ends it
+    /// the same way. An error reply is returned as an `io::Error`.
+    pub fn execute(&mut self, message: Msg<'_>, addr: &NetlinkAddr) -> io::Result<Vec<OwnedMsg>> {
+        let mut header = message.header();
+        header.ack();
+        let seq = header.msg_seq();
+        self.send(Msg::new(header, message.payload().clone()), addr)?;
+
+        let mut replies = vec![];
+        loop {
+            let (_, messages) = self.recv_owned()?;
+            for reply in messages {
+                {
+                    let msg = reply.msg();
+                    if msg.header().msg_seq() != seq {
+                        continue;
+                    }
+                    // an error reply, or a Done ending a failed dump
+                    if let Some(e) = msg.as_os_error() {
+                        return Err(e);
+                    }
+                    match *msg.payload() {
+                        Payload::Ack(_) => return Ok(replies),
+                        _ if msg.header().msg_type() == MsgType::Done => return Ok(replies),
+                        _ => {}
+                    }
+                }
+                replies.push(reply);
+            }
+        }
+    }
+
+    /// Receives until an ACK, error or `Done` message arrives, discarding
+    /// the data messages before it and anything after it in its datagram.
+    pub fn recv_control(&mut self) -> io::Result<ControlOutcome> {
+        loop {
+            let (_, messages) = self.recv_owned()?;
+            for reply in &messages {
+                let msg = reply.msg();
+                if let Some(e) = msg.as_os_error() {
+                    return Ok(ControlOutcome::Error(e));
+                }
+                match *msg.payload() {
+                    Payload::Ack(_) => return Ok(ControlOutcome::Ack),
+                    _ if msg.header().msg_type() == MsgType::Done => {
+                        return Ok(ControlOutcome::Done)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Starts a synchronous exchange under a fresh sequence number. The
+    /// guard holds the socket until the exchange is done:
+    ///
+    /// ```compile_fail
+    /// # use netlink_rs::Protocol;
+    /// # use netlink_rs::socket::Socket;
+    /// let mut socket = Socket::new(Protocol::Usersock).unwrap();
+    /// let first = socket.begin_request();
+    /// let second = socket.begin_request();
+    /// drop(first);
+    /// ```
+    pub fn begin_request(&mut self) -> RequestGuard<'_> {
+        let seq = self.next_seq;
+        // 0 marks messages that are not requests, see validate_request
+        self.next_seq = self.next_seq.wrapping_add(1).max(1);
+        RequestGuard { socket: self, seq }
+    }
+
+    /// Sequence number the next `begin_request` hands out. Only requests
+    /// sent through a `RequestGuard` use the counter; `send`, `send_multi`
+    /// and `execute` send the sequence number the header already carries.
+    pub fn seq(&self) -> u32 {
+        self.next_seq
+    }
+
+    /// Restarts the counter behind `begin_request` at `seq`. As 0 is never
+    /// handed out, setting 0 starts at 1.
+    pub fn set_seq(&mut self, seq: u32) {
+        self.next_seq = seq.max(1);
+    }
+
+    /// Returns and clears the socket's pending error (`SO_ERROR`), such as
+    /// the ENOBUFS netlink reports after dropping messages.
+    pub fn take_error(&self) -> io::Result<Option<io::Error>> {
+        match self.inner.getsockopt::<c_int>(SOL_SOCKET, SO_ERROR)? {
+            0 => Ok(None),
+            errno => Ok(Some(io::Error::from_raw_os_error(errno))),
+        }
+    }
+
+    /// Upper bound on the datagrams the socket can currently receive, its
+    /// `SO_RCVBUF` size. The kernel doubles the value set and counts its
+    /// own per-datagram overhead against it, so actual datagrams are
+    /// smaller. Like `MAX_NETLINK_MSG_SIZE` this informs buffer sizes; the
+    /// value changes with the socket option.
+    pub fn max_msg_size(&self) -> io::Result<usize> {
+        let rcvbuf = self.inner.getsockopt::<c_int>(SOL_SOCKET, SO_RCVBUF)?;
+        Ok(rcvbuf as usize)
+    }
+
+    /// Suggests how large a buffer to allocate for receiving a dump: the
+    /// socket's receive buffer size (`SO_RCVBUF`), which bounds a single
+    /// datagram. Falls back to the size of the internal buffer if the option
+    /// cannot be read.
+    pub fn suggest_dump_buffer(&self) -> usize {
+        match self.inner.getsockopt::<c_int>(SOL_SOCKET, SO_RCVBUF) {
+            Ok(n) if n > 0 => n as usize,
+            _ => self.buf.len(),
+        }
+    }
+
+    /// Returns the type of the next queued message without consuming the
+    /// datagram, so a following `recv` still returns it.
+    pub fn peek_type(&mut self) -> io::Result<MsgType> {
+        self.unread = None;
+        let buffer = &mut self.buf[..nlmsg_header_length()];
+        let (_, n) = self.inner.recvfrom_into(buffer, MSG_PEEK)?;
+        let (hdr, _) = NlMsgHeader::from_bytes(&buffer[..n])?;
+        Ok(hdr.msg_type())
+    }
+}
+
+/// Fails with `NotConnected` if the socket was closed
+impl TryFrom<Socket> for OwnedFd {
+    type Error = io::Error;
+
+    fn try_from(socket: Socket) -> io::Result<OwnedFd> {
+        let fd = socket.inner.into_fd()?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+}
+
+/// Moves the thread back to the network namespace it was in, by `restore`
+/// or else when dropped
+struct NetnsGuard {
+    prev: Option<File>,
+}
+
+impl NetnsGuard {
+    fn enter(netns_fd: RawFd) -> io::Result<NetnsGuard> {
+        // setns moves the calling thread only
+        let prev = File::open("/proc/thread-self/ns/net")?;
+        if unsafe { libc::setns(netns_fd, CLONE_NEWNET) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(NetnsGuard { prev: Some(prev) })
+    }
+
+    fn restore(mut self) -> io::Result<()> {
+        match self.prev.take() {
+            Some(prev) => setns_net(&prev),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for NetnsGuard {
+    fn drop(&mut self) {
+        if let Some(prev) = self.prev.take() {
+            // the error cannot be reported from here, and going on in the
+            // other namespace would hand the caller its sockets and routes
+            if setns_net(&prev).is_err() {
+                process::abort();
+            }
+        }
+    }
+}
+
+fn setns_net(ns: &File) -> io::Result<()> {
+    if unsafe { libc::setns(ns.as_raw_fd(), CLONE_NEWNET) } < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// The inner error of a failed `add_membership` or `drop_membership`,
+/// naming the group. The setsockopt error, with its errno, is the source:
+///
+/// ```no_run
+/// # use netlink_rs::Protocol;
+/// # use netlink_rs::socket::{MembershipError, Socket};
+/// let socket = Socket::new(Protocol::Route).unwrap();
+/// if let Err(e) = socket.add_membership(1000) {
+///     let errno = e
+///         .get_ref()
+///         .and_then(|e| e.downcast_ref::<MembershipError>())
+///         .and_then(|e| e.os_error().raw_os_error());
+///     assert_eq!(errno, Some(libc::EINVAL));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MembershipError {
+    action: &'static str,
+    group: u32,
+    error: io::Error,
+}
+
+impl MembershipError {
+    pub fn group(&self) -> u32 {
+        self.group
+    }
+
+    /// The error setsockopt failed with
+    pub fn os_error(&self) -> &io::Error {
+        &self.error
+    }
+}
+
+impl fmt::Display for MembershipError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "cannot {} multicast group {}: {}",
+            self.action, self.group, self.error
+        )
+    }
+}
+
+impl error::Error for MembershipError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+fn membership_error(action: &'static str, group: u32, e: io::Error) -> io::Error {
+    io::Error::new(
+        e.kind(),
+        MembershipError {
+            action,
+            group,
+            error: e,
+        },
+    )
+}
+
+// Errors socket(2) fails with when netlink is not available at all
+fn is_unavailable(e: &io::Error) -> bool {
+    match e.raw_os_error() {
+        Some(errno) => [EPERM, EACCES, EAFNOSUPPORT, EPROTONOSUPPORT].contains(&errno),
+        None => false,
+    }
+}
+
+// Up to the first 32 bytes in hex, to show in parse errors
+fn hex_prefix(bytes: &[u8]) -> String {
+    let shown = &bytes[..bytes.len().min(32)];
+    let hex: Vec<String> = shown.iter().map(|b| format!("{:02x}", b)).collect();
+    let more = if bytes.len() > shown.len() { " .." } else { "" };
+    format!("{}{}", hex.join(" "), more)
+}
+
+// from linux/capability.h
+const CAP_NET_ADMIN: u32 = 12;
+
+/// Whether the process has `CAP_NET_ADMIN` in its effective set, which most
+/// requests changing network configuration need. Reads `/proc/self/status`
+/// and answers false if that cannot be read.
+pub fn has_net_admin() -> bool {
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_NET_ADMIN) != 0)
+}
+
+/// Opt-in early check before a privileged request: fails with
+/// `PermissionDenied` naming the missing capability rather than the bare
+/// EPERM the kernel would reply with.
+pub fn require_net_admin() -> io::Result<()> {
+    if has_net_admin() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            "CAP_NET_ADMIN is required, run as root or grant the capability",
+        ))
+    }
+}
+
+/// Concatenates encoded messages into one datagram, padding each to the
+/// message alignment so the next starts where a receiver looks for it
+pub fn concat_aligned(msgs: &[&[u8]]) -> Vec<u8> {
+    SocketConfig::new().concat(msgs)
+}
+
+/// Message framing parameters. Netlink always aligns to `NLMSG_ALIGNTO`,
+/// the default; other alignments are only for test harnesses and
+/// protocols that frame messages the same way.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SocketConfig {
+    align_to: usize,
+}
+
+impl Default for SocketConfig {
+    fn default() -> SocketConfig {
+        SocketConfig::new()
+    }
+}
+
+impl SocketConfig {
+    pub const fn new() -> SocketConfig {
+        SocketConfig {
+            align_to: NLMSG_ALIGNTO,
+        }
+    }
+
+    /// Sets the message alignment, a power of two of at most the 16 byte
+    /// header size so that payloads still start right after the header
+    pub fn align_to(&mut self, align_to: usize) -> io::Result<&mut SocketConfig> {
+        if !align_to.is_power_of_two() || align_to > size_of::<NlMsgHeader>() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("alignment {} is not a power of two up to 16", align_to),
+            ));
+        }
+        self.align_to = align_to;
+        Ok(self)
+    }
+
+    pub fn alignment(&self) -> usize {
+        self.align_to
+    }
+
+    /// `len` rounded up to the alignment, as NLMSG_ALIGN
+    // #define NLMSG_ALIGN(len) ( ((len)+NLMSG_ALIGNTO-1) & ~(NLMSG_ALIGNTO-1) )
+    pub fn align(&self, len: usize) -> usize {
+        (len + (self.align_to - 1)) & !(self.align_to - 1)
+    }
+
+    /// The aligned header size, as NLMSG_HDRLEN
+    pub fn header_length(&self) -> usize {
+        self.align(size_of::<NlMsgHeader>())
+    }
+
+    /// The `msg_length` of a message with `len` payload bytes, as
+    /// NLMSG_LENGTH
+    pub fn length(&self, len: usize) -> usize {
+        len + self.align(self.header_length())
+    }
+
+    /// Like `Msg::wire_len`, padding to this alignment
+    pub fn wire_len(&self, msg: &Msg) -> usize {
+        self.align(self.length(msg.payload.len()))
+    }
+
+    /// Like `concat_aligned`, padding to this alignment
+    pub fn concat(&self, msgs: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for msg in msgs {
+            bytes.extend_from_slice(msg);
+            let padded = self.align(bytes.len());
+            bytes.resize(padded, 0);
+        }
+        bytes
+    }
+
+    /// Splits a datagram built by `concat` back into its messages
+    pub fn split<'a>(&self, datagram: &'a [u8]) -> io::Result<Vec<Msg<'a>>> {
+        MsgIter::with_config(datagram, self).collect()
+    }
+}
+
+// Copies of the messages of a received datagram, without NOOPs
+fn copy_datagram(
+    datagram: &[u8],
+    trace: &Option<Trace>,
+    config: &SocketConfig,
+) -> io::Result<Vec<OwnedMsg>> {
+    let mut messages = vec![];
+    let mut n = 0;
+    while n < datagram.len() {
+        let (msg, num_bytes) = OwnedMsg::from_bytes_with(&datagram[n..], config)?;
+        n += num_bytes;
+        if let Some(ref trace) = *trace {
+            trace(Direction::Recv, &msg.msg());
+        }
+        match msg.header().msg_type() {
+            MsgType::Noop => {}
+            // as in parse_datagram_checked
+            MsgType::Overrun => return Err(io::Error::from_raw_os_error(ENOBUFS)),
+            _ => messages.push(msg),
+        }
+    }
+    Ok(messages)
+}
+
+// Messages of a received datagram up to the first Done, without NOOPs. A
+// malformed message or an NLMSG_OVERRUN fails the whole datagram rather
+// than hiding the rest.
+fn parse_datagram<'a>(
+    datagram: &'a [u8],
+    trace: &Option<Trace>,
+    config: &SocketConfig,
+) -> io::Result<Vec<Msg<'a>>> {
+    parse_datagram_checked(datagram, trace, config)
+        .into_iter()
+        .collect()
+}
+
+// Like parse_datagram, ending with the error if a message is malformed
+fn parse_datagram_checked<'a>(
+    datagram: &'a [u8],
+    trace: &Option<Trace>,
+    config: &SocketConfig,
+) -> Vec<io::Result<Msg<'a>>> {
+    let mut messages = vec![];
+
+    let mut n = 0;
+    while n < datagram.len() {
+        let msg = match Msg::from_bytes_with(&datagram[n..], config) {
+            Ok((msg, _)) => msg,
+            Err(e) => {
+                messages.push(Err(e));
+                break;
+            }
+        };
+        // skip the padding before the next message, as MsgIter does
+        n += config.align(msg.header().msg_length() as usize);
+        if let Some(ref trace) = *trace {
+            trace(Direction::Recv, &msg);
+        }
+        match msg.header().msg_type() {
+            MsgType::Done => break,
+            MsgType::Noop => {}
+            // data was lost, like the ENOBUFS the socket reports
+            MsgType::Overrun => {
+                messages.push(Err(io::Error::from_raw_os_error(ENOBUFS)));
+                break;
+            }
+            _ => messages.push(Ok(msg)),
+        }
+    }
+
+    messages
+}
+
+// #define NLMSG_HDRLEN     ((int) NLMSG_ALIGN(sizeof(struct nlmsghdr)))
+#[inline]
+fn nlmsg_header_length() -> usize {
+    SocketConfig::new().header_length()
+}
+
+// NLMSG_LENGTH()
+//        Given the payload length, len, this macro returns the aligned
+//        length to store in the nlmsg_len field of the nlmsghdr.
+// #define NLMSG_LENGTH(len) ((len)+NLMSG_ALIGN(NLMSG_HDRLEN))
+#[inline]
+fn nlmsg_length(len: usize) -> usize {
+    SocketConfig::new().length(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::{NativeEndian, WriteBytesExt};
+    use std::io::Write;
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use Protocol;
+
+    // a usersock socket, or None to skip a test where netlink is unavailable
+    fn try_socket() -> Option<Socket> {
+        try_protocol(Protocol::Usersock)
+    }
+
+    // a socket of `protocol`, None where the sandbox refuses to open one
+    fn try_protocol(protocol: Protocol) -> Option<Socket> {
+        Socket::try_new(protocol).unwrap()
+    }
+
+    // two usersock sockets on kernel assigned pids, so parallel test runs
+    // cannot collide
+    fn usersock_pair() -> Option<(Socket, NetlinkAddr, Socket, NetlinkAddr)> {
+        let send = try_socket()?;
+        let recv = try_socket()?;
+        let send_addr = send.bind_auto().unwrap();
+        let recv_addr = recv.bind_auto().unwrap();
+        Some((send, send_addr, recv, recv_addr))
+    }
+
+    #[test]
+    fn test_send_recv() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let bytes = [0, 1, 2, 3, 4, 5];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(6).seq(1).pid(recv_addr.pid());
+        let msg = Msg::new(shdr, Payload::Data(&bytes));
+
+        send.send(msg, &recv_addr).unwrap();
+
+        let (addr, vec) = &recv.recv().unwrap();
+        assert_eq!(vec.len(), 1);
+
+        let msg = vec.first().unwrap();
+        assert_eq!(addr, &send_addr);
+        if let &Payload::Data(b) = msg.payload() {
+            assert_eq!(b, &bytes);
+        } else {
+            panic!("msg is not Data enum");
+        }
+    }
+
+    #[test]
+    fn test_recv_skips_noop() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        let first = Msg::new(shdr, Payload::Data(&[1, 1, 1, 1]));
+        let noop = Msg::new(NlMsgHeader::user_defined(1, 0), Payload::None);
+        let second = Msg::new(shdr, Payload::Data(&[2, 2, 2, 2]));
+        send.send_multi(vec![first, noop, second], &recv_addr)
+            .unwrap();
+
+        let (_, messages) = recv.recv().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].payload(), &Payload::Data(&[1, 1, 1, 1]));
+        assert_eq!(messages[1].payload(), &Payload::Data(&[2, 2, 2, 2]));
+    }
+
+    #[test]
+    fn test_send_recv_vectored() {
+        let Some((send, send_addr, recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        let body = [1, 2, 3, 4];
+        let bufs = [IoSlice::new(shdr.bytes()), IoSlice::new(&body)];
+        assert_eq!(send.send_vectored(&bufs, &recv_addr).unwrap(), 20);
+
+        let mut head = [0u8; 16];
+        let mut tail = [0u8; 8];
+        let (addr, len) = {
+            let mut bufs = [IoSliceMut::new(&mut head), IoSliceMut::new(&mut tail)];
+            recv.recv_vectored(&mut bufs).unwrap()
+        };
+        assert_eq!(addr, send_addr);
+        assert_eq!(len, 20);
+        assert_eq!(&head[..], shdr.bytes());
+        assert_eq!(&tail[..4], &body);
+    }
+
+    #[test]
+    fn test_send_bytes() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        // an NLMSG_DONE with NLM_F_MULTI, seq 5 and pid 7, as the kernel sends
+        let mut bytes = vec![];
+        bytes.write_u32::<NativeEndian>(20).unwrap();
+        bytes.write_u16::<NativeEndian>(3).unwrap();
+        bytes.write_u16::<NativeEndian>(2).unwrap();
+        bytes.write_u32::<NativeEndian>(5).unwrap();
+        bytes.write_u32::<NativeEndian>(7).unwrap();
+        bytes.write_i32::<NativeEndian>(0).unwrap();
+        assert_eq!(send.send_bytes(&bytes, &recv_addr).unwrap(), 20);
+
+        let (addr, raw, messages) = recv.recv_raw().unwrap();
+        assert_eq!(addr, send_addr);
+        assert_eq!(raw, &bytes[..]);
+        // recv_raw leaves out the Done
+        assert!(messages.is_empty());
+    }
+
+    #[test]
+    fn test_recv_overrun_msg() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        let data = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&[1; 4]));
+        let overrun = Msg::new(
+            NlMsgHeader::user_defined(MsgType::Overrun.into(), 0),
+            Payload::None,
+        );
+        send.send_multi(vec![data.clone(), overrun.clone()], &recv_addr)
+            .unwrap();
+        send.send_multi(vec![data.clone(), overrun.clone()], &recv_addr)
+            .unwrap();
+        send.send_multi(vec![data.clone(), overrun.clone()], &recv_addr)
+            .unwrap();
+        send.send_multi(vec![data, overrun], &recv_addr).unwrap();
+
+        let (_, messages) = recv.recv_checked().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].is_ok());
+        let err = messages[1].as_ref().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+
+        let err = recv.recv().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+
+        // the owned paths, such as into_event_stream, report it too
+        let err = recv.recv_batch(1).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+
+        assert!(recv.recv_one().unwrap().1.is_some());
+        let err = recv.recv_one().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+    }
+
+    #[test]
+    fn test_recv_checked() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        let mut bytes = Msg::new(shdr, Payload::Data(&[1, 1, 1, 1]))
+            .bytes()
+            .unwrap();
+        // a header claiming more bytes than the datagram holds
+        let mut corrupt = NlMsgHeader::request();
+        corrupt.data_length(64);
+        bytes.extend_from_slice(corrupt.bytes());
+        send.inner
+            .sendto(&bytes, 0, &recv_addr.as_sockaddr())
+            .unwrap();
+
+        send.inner
+            .sendto(&bytes, 0, &recv_addr.as_sockaddr())
+            .unwrap();
+
+        let (_, messages) = recv.recv_checked().unwrap();
+        assert_eq!(messages.len(), 2);
+        let first = messages[0].as_ref().unwrap();
+        assert_eq!(first.payload(), &Payload::Data(&[1, 1, 1, 1]));
+        let err = messages[1].as_ref().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // recv used to stop at the corrupt message and return only the first
+        let err = recv.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_recv_one() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4).multipart();
+        let messages = vec![
+            Msg::new(shdr, Payload::Data(&[1, 1, 1, 1])),
+            Msg::new(shdr, Payload::Data(&[2, 2, 2, 2])),
+            Msg::new(NlMsgHeader::done(), Payload::None),
+            Msg::new(shdr, Payload::Data(&[4, 4, 4, 4])),
+        ];
+        send.send_multi(messages, &recv_addr).unwrap();
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        send.send(Msg::new(shdr, Payload::Data(&[3, 3, 3, 3])), &recv_addr)
+            .unwrap();
+
+        for expected in &[[1u8; 4], [2; 4]] {
+            let (addr, msg) = recv.recv_one().unwrap();
+            assert_eq!(addr, send_addr);
+            assert_eq!(msg.unwrap().payload(), &Payload::Data(expected));
+        }
+        assert!(recv.recv_one().unwrap().1.is_none());
+        // the message after the Done is dropped, so this receives the
+        // second datagram
+        let (_, msg) = recv.recv_one().unwrap();
+        assert_eq!(msg.unwrap().payload(), &Payload::Data(&[3, 3, 3, 3]));
+    }
+
+    #[test]
+    fn test_recv_raw() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let bytes = [9, 8, 7, 6];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4).seq(3);
+        let msg = Msg::new(shdr, Payload::Data(&bytes));
+        let expected = msg.bytes().unwrap();
+        send.send(msg, &recv_addr).unwrap();
+
+        let (addr, raw, messages) = recv.recv_raw().unwrap();
+        assert_eq!(addr, send_addr);
+        assert_eq!(raw, expected);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload(), &Payload::Data(&bytes));
+    }
+
+    #[test]
+    fn test_recv_until() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        for seq in 1..3 {
+            let bytes = [seq as u8; 4];
+            let mut shdr = NlMsgHeader::request();
+            shdr.data_length(4).seq(seq);
+            send.send(Msg::new(shdr, Payload::Data(&bytes)), &recv_addr)
+                .unwrap();
+        }
+
+        let start = Instant::now();
+        let window = Duration::from_millis(50);
+        let datagrams = recv.recv_until(start + window).unwrap();
+        assert!(start.elapsed() >= window);
+        assert_eq!(datagrams.len(), 2);
+        for (i, &(addr, ref messages)) in datagrams.iter().enumerate() {
+            assert_eq!(addr, send_addr);
+            assert_eq!(messages.len(), 1);
+            let msg = messages[0].msg();
+            assert_eq!(msg.payload(), &Payload::Data(&[i as u8 + 1; 4]));
+        }
+
+        // a header claiming more bytes than were sent fails the whole call
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        send.send(Msg::new(shdr, Payload::Data(&[1; 4])), &recv_addr)
+            .unwrap();
+        send.send_bytes(shdr.bytes(), &recv_addr).unwrap();
+        let err = recv.recv_until(Instant::now() + window).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_config_align_8() {
+        let mut config = SocketConfig::new();
+        assert_eq!(config.alignment(), 4);
+        config.align_to(8).unwrap();
+        assert_eq!(config.align(17), 24);
+        assert_eq!(config.header_length(), 16);
+        assert_eq!(config.length(4), 20);
+
+        let mut hdr = NlMsgHeader::user_defined(20, 0);
+        hdr.data_length(4);
+        let first = Msg::new(hdr, Payload::Data(&[1, 2, 3, 4])).bytes().unwrap();
+        let second = Msg::new(NlMsgHeader::done(), Payload::None)
+            .bytes()
+            .unwrap();
+        let datagram = config.concat(&[&first, &second]);
+        // the 20 byte message is padded to 24, not to 20 as with 4
+        assert_eq!(datagram.len(), 24 + 16);
+        assert_eq!(&datagram[20..24], &[0; 4]);
+
+        let msgs = config.split(&datagram).unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].payload(), &Payload::Data(&[1, 2, 3, 4]));
+        assert_eq!(msgs[1].header().msg_type(), MsgType::Done);
+
+        for bad in &[0, 3, 32] {
+            let err = SocketConfig::new().align_to(*bad).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn test_socket_config_framing() {
+        if try_socket().is_none() {
+            return;
+        }
+        let mut config = SocketConfig::new();
+        config.align_to(8).unwrap();
+        let send = Socket::with_config(Protocol::Usersock, &config).unwrap();
+        let mut recv = Socket::with_config(Protocol::Usersock, &config).unwrap();
+        assert_eq!(recv.config().alignment(), 8);
+        send.bind_auto().unwrap();
+        let recv_addr = recv.bind_auto().unwrap();
+
+        let bytes = [1, 2, 3, 4];
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&bytes));
+        assert_eq!(
+            send.validate_batch(&[msg.clone(), msg.clone()]).unwrap(),
+            48
+        );
+        send.send_multi(vec![msg.clone(), msg.clone()], &recv_addr)
+            .unwrap();
+
+        let (_, raw, msgs) = recv.recv_raw().unwrap();
+        // each 20 byte message padded to 24
+        assert_eq!(raw.len(), 48);
+        assert_eq!(msgs, vec![msg.clone(), msg]);
+    }
+
+    #[test]
+    fn test_send_multi_recv() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let bytes = [0, 1, 2, 3, 4, 5];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(6).multipart().seq(1).pid(recv_addr.pid());
+        let msg = Msg::new(shdr, Payload::Data(&bytes));
+        let msg2 = msg.clone();
+
+        let mut donehdr = NlMsgHeader::done();
+        donehdr.pid(recv_addr.pid());
+        let donemsg = Msg::new(donehdr, Payload::None);
+
+        send.send_multi(vec![msg, msg2, donemsg], &recv_addr)
+            .unwrap();
+
+        let (addr, vec) = &recv.recv().unwrap();
+        assert_eq!(vec.len(), 2);
+
+        let msg = vec.first().unwrap();
+        assert_eq!(addr, &send_addr);
+        if let &Payload::Data(b) = msg.payload() {
+            assert_eq!(b, &bytes);
+        } else {
+            panic!("msg is not Data enum");
+        }
+    }
+
+    #[test]
+    fn test_send_and_recv() {
+        let Some((mut client, client_addr, server, server_addr)) = usersock_pair() else {
+            return;
+        };
+
+        // queue the "response" first, the request is left unread
+        let reply = [9, 8, 7, 6];
+        let mut rhdr = NlMsgHeader::user_defined(21, 4);
+        rhdr.seq(1).pid(server_addr.pid());
+        server
+            .send(Msg::new(rhdr, Payload::Data(&reply)), &client_addr)
+            .unwrap();
+
+        let request = [1, 2, 3, 4];
+        let mut qhdr = NlMsgHeader::user_defined(20, 4);
+        qhdr.seq(1).pid(client_addr.pid());
+        let msg = Msg::new(qhdr, Payload::Data(&request));
+
+        let (addr, vec) = client.send_and_recv(msg, &server_addr).unwrap();
+        assert_eq!(addr, server_addr);
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec[0].payload(), &Payload::Data(&reply));
+    }
+
+    #[test]
+    fn test_execute_with_echo() {
+        let Some((mut client, client_addr, server, server_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut qhdr = NlMsgHeader::user_defined(20, 4);
+        qhdr.seq(7).echo();
+        // the replies are queued first, the request is left unread
+        let mut ehdr = NlMsgHeader::user_defined(21, 4);
+        ehdr.seq(7);
+        let mut other = ehdr;
+        other.seq(8);
+        let mut ahdr = NlMsgHeader::error();
+        ahdr.seq(7);
+        let replies = vec![
+            Msg::new(other, Payload::Data(&[0, 0, 0, 0])),
+            Msg::new(ehdr, Payload::Data(&[5, 5, 5, 5])),
+            Msg::new(ahdr, Payload::Ack(qhdr)),
+        ];
+        server.send_multi(replies, &client_addr).unwrap();
+
+        let msg = Msg::new(qhdr, Payload::Data(&[1, 2, 3, 4]));
+        let echoed = client.execute(msg, &server_addr).unwrap();
+        assert_eq!(echoed.len(), 1);
+        assert_eq!(echoed[0].msg().payload(), &Payload::Data(&[5, 5, 5, 5]));
+
+        let mut ehdr = NlMsgHeader::error();
+        ehdr.seq(7);
+        let reply = Msg::new(ehdr, Payload::Err(-libc::EPERM, qhdr));
+        server.send(reply, &client_addr).unwrap();
+        let msg = Msg::new(qhdr, Payload::Data(&[1, 2, 3, 4]));
+        let err = client.execute(msg, &server_addr).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+
+        let mut dhdr = NlMsgHeader::done();
+        dhdr.data_length(4).seq(7);
+        server
+            .send(Msg::new(dhdr, Payload::Done(-libc::EINTR)), &client_addr)
+            .unwrap();
+        let msg = Msg::new(qhdr, Payload::Data(&[1, 2, 3, 4]));
+        let err = client.execute(msg, &server_addr).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINTR));
+    }
+
+    #[test]
+    fn test_execute_dump() {
+        let Some((mut client, client_addr, server, server_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut qhdr = NlMsgHeader::user_defined(20, 4);
+        qhdr.seq(9).dump();
+        let mut rhdr = NlMsgHeader::user_defined(21, 4);
+        rhdr.seq(9).multipart();
+        let mut dhdr = NlMsgHeader::done();
+        dhdr.data_length(4).seq(9);
+        let replies = vec![
+            Msg::new(rhdr, Payload::Data(&[5, 5, 5, 5])),
+            Msg::new(dhdr, Payload::Done(0)),
+        ];
+        server.send_multi(replies, &client_addr).unwrap();
+
+        let msg = Msg::new(qhdr, Payload::Data(&[1, 2, 3, 4]));
+        let dump = client.execute(msg.clone(), &server_addr).unwrap();
+        assert_eq!(dump.len(), 1);
+        assert_eq!(dump[0].msg().payload(), &Payload::Data(&[5, 5, 5, 5]));
+
+        // a Done without a status
+        let mut dhdr = NlMsgHeader::done();
+        dhdr.seq(9);
+        server
+            .send(Msg::new(dhdr, Payload::None), &client_addr)
+            .unwrap();
+        assert!(client.execute(msg, &server_addr).unwrap().is_empty());
+
+        // the kernel answers a dump with NLM_F_ACK with a Done alone
+        let Some(mut route) = try_protocol(Protocol::Route) else {
+            return;
+        };
+        let ifinfomsg = [0u8; 16];
+        let mut hdr = NlMsgHeader::user_defined(18, ifinfomsg.len() as u32);
+        hdr.dump().seq(1);
+        let request = Msg::new(hdr, Payload::Data(&ifinfomsg));
+        let links = route.execute(request, &NetlinkAddr::new(0, 0)).unwrap();
+        assert!(!links.is_empty());
+    }
+
+    #[test]
+    fn test_recv_control() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let mut shdr = NlMsgHeader::user_defined(21, 4);
+        shdr.seq(3);
+        let replies = vec![
+            Msg::new(shdr, Payload::Data(&[1, 2, 3, 4])),
+            Msg::new(NlMsgHeader::error(), Payload::Ack(shdr)),
+        ];
+        send.send_multi(replies, &recv_addr).unwrap();
+        match recv.recv_control().unwrap() {
+            ControlOutcome::Ack => {}
+            other => panic!("unexpected outcome {:?}", other),
+        }
+
+        let reply = Msg::new(NlMsgHeader::error(), Payload::Err(-libc::EEXIST, shdr));
+        send.send(reply, &recv_addr).unwrap();
+        match recv.recv_control().unwrap() {
+            ControlOutcome::Error(e) => assert_eq!(e.raw_os_error(), Some(libc::EEXIST)),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+
+        send.send(Msg::new(NlMsgHeader::done(), Payload::None), &recv_addr)
+            .unwrap();
+        match recv.recv_control().unwrap() {
+            ControlOutcome::Done => {}
+            other => panic!("unexpected outcome {:?}", other),
+        }
+
+        let mut dhdr = NlMsgHeader::done();
+        dhdr.data_length(4);
+        let failed = Msg::new(dhdr, Payload::Done(-libc::EINTR));
+        send.send(failed, &recv_addr).unwrap();
+        match recv.recv_control().unwrap() {
+            ControlOutcome::Error(e) => assert_eq!(e.raw_os_error(), Some(libc::EINTR)),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut handlers: HashMap<MsgType, Handler> = HashMap::new();
+        for &ty in &[20, 21] {
+            let seen = seen.clone();
+            handlers.insert(
+                MsgType::UserDefined(ty),
+                Box::new(move |m: &Msg| seen.lock().unwrap().push((ty, m.header().msg_seq()))),
+            );
+        }
+
+        let messages = vec![
+            Msg::with_attrs(MsgType::UserDefined(21), 0, 1, 0, &[]),
+            Msg::with_attrs(MsgType::UserDefined(22), 0, 2, 0, &[]),
+            Msg::with_attrs(MsgType::UserDefined(20), 0, 3, 0, &[]),
+        ];
+        send.send_multi(messages, &recv_addr).unwrap();
+        recv.dispatch(&mut handlers).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![(21, 1), (20, 3)]);
+    }
+
+    #[test]
+    fn test_request_guard() {
+        let Some((mut client, client_addr, mut server, server_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let first = client.begin_request().seq();
+        let mut guard = client.begin_request();
+        assert_eq!(guard.seq(), first + 1);
+
+        let msg = Msg::with_attrs(MsgType::UserDefined(20), 1, 0, 0, &[]);
+        guard.send(msg, &server_addr).unwrap();
+        let (_, requests) = server.recv_owned().unwrap();
+        assert_eq!(requests[0].header().msg_seq(), first + 1);
+
+        // a stale reply, then the one to the guarded request
+        let stale = Msg::with_attrs(MsgType::UserDefined(21), 0, first, 0, &[1, 1, 1, 1]);
+        server.send(stale, &client_addr).unwrap();
+        let reply = Msg::with_attrs(MsgType::UserDefined(21), 0, first + 1, 0, &[2, 2, 2, 2]);
+        server.send(reply, &client_addr).unwrap();
+
+        let replies = guard.recv().unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].msg().payload(), &Payload::Data(&[2, 2, 2, 2]));
+    }
+
+    #[test]
+    fn test_trace() {
+        let Some((mut send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let log = Arc::new(Mutex::new(vec![]));
+        let send_log = log.clone();
+        send.set_trace(Box::new(move |d, m| {
+            send_log.lock().unwrap().push((d, m.header()))
+        }));
+        let recv_log = log.clone();
+        recv.set_trace(Box::new(move |d, m| {
+            recv_log.lock().unwrap().push((d, m.header()))
+        }));
+
+        let bytes = [0, 1, 2, 3];
+        let mut shdr = NlMsgHeader::user_defined(20, 4);
+        shdr.seq(1).pid(recv_addr.pid());
+        send.send(Msg::new(shdr, Payload::Data(&bytes)), &recv_addr)
+            .unwrap();
+        recv.recv().unwrap();
+
+        let log = log.lock().unwrap();
+        assert_eq!(*log, vec![(Direction::Send, shdr), (Direction::Recv, shdr)]);
+
+        // an untraced socket stays silent
+        send.clear_trace();
+        send.send(Msg::new(shdr, Payload::Data(&bytes)), &recv_addr)
+            .unwrap();
+        assert_eq!(log.len(), 2);
+    }
+
+    #[test]
+    fn test_peek_type() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let bytes = [0, 1, 2, 3, 4, 5];
+        let mut shdr = NlMsgHeader::user_defined(20, 6);
+        shdr.seq(1).pid(recv_addr.pid());
+        let msg = Msg::new(shdr, Payload::Data(&bytes));
+
+        send.send(msg, &recv_addr).unwrap();
+
+        assert_eq!(recv.peek_type().unwrap(), MsgType::UserDefined(20));
+        assert_eq!(recv.peek_type().unwrap(), MsgType::UserDefined(20));
+
+        let (_, vec) = recv.recv().unwrap();
+        assert_eq!(vec.len(), 1);
+        assert_eq!(vec[0].header(), shdr);
+        assert_eq!(vec[0].payload(), &Payload::Data(&bytes));
+    }
+
+    #[test]
+    fn test_suggest_dump_buffer() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        socket
+            .inner
+            .setsockopt(SOL_SOCKET, SO_RCVBUF, 65536 as c_int)
+            .unwrap();
+        // Linux doubles the requested value for bookkeeping overhead
+        assert!(socket.suggest_dump_buffer() >= 65536);
+    }
+
+    #[test]
+    fn test_new_in_netns() {
+        let netns = File::open("/proc/thread-self/ns/net").unwrap();
+        match Socket::new_in_netns(Protocol::Usersock, netns.as_raw_fd()) {
+            Ok(socket) => {
+                assert_eq!(socket.protocol(), Protocol::Usersock as i32);
+                // back in the namespace the test started in
+                let now = fs::metadata("/proc/thread-self/ns/net").unwrap();
+                assert_eq!(now.ino(), netns.metadata().unwrap().ino());
+            }
+            // joining a namespace, even the current one, needs CAP_SYS_ADMIN
+            Err(ref e) if e.raw_os_error() == Some(EPERM) => {}
+            Err(ref e) if is_unavailable(e) => {}
+            Err(e) => panic!("{}", e),
+        }
+
+        // not a namespace descriptor
+        let Err(err) = Socket::new_in_netns(Protocol::Usersock, -1) else {
+            panic!("joined an invalid namespace");
+        };
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    fn test_last_sent_seq() {
+        let Some((mut send, _, _recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        assert_eq!(send.last_sent_seq(), 0);
+
+        let mut hdr = NlMsgHeader::user_defined(20, 0);
+        hdr.seq(42);
+        send.send(Msg::new(hdr, Payload::None), &recv_addr).unwrap();
+        assert_eq!(send.last_sent_seq(), 42);
+
+        // a guard's sequence number replaces the message's
+        let mut guard = send.begin_request();
+        guard
+            .send(Msg::new(hdr, Payload::None), &recv_addr)
+            .unwrap();
+        let seq = guard.seq();
+        assert_eq!(send.last_sent_seq(), seq);
+    }
+
+    #[test]
+    fn test_recv_batch() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        for i in 0..3 {
+            let payload = [i; 4];
+            let msg = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&payload));
+            send.send(msg, &recv_addr).unwrap();
+        }
+
+        let (messages, stats) = recv.recv_batch(8).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(stats.datagrams(), 3);
+        assert_eq!(stats.bytes(), 3 * 20);
+    }
+
+    #[test]
+    fn test_recv_from_kernel() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&[1; 4]));
+        send.send(msg, &recv_addr).unwrap();
+
+        let err = recv.recv_from_kernel().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn test_recv_closed() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        // netlink refuses to send an empty datagram
+        let err = send.send_bytes(&[], &recv_addr).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENODATA));
+
+        recv.close().unwrap();
+        let err = recv.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn test_recv_empty_datagram() {
+        // netlink cannot send one, a Unix datagram socket stands in
+        let (a, b) = ::std::os::unix::net::UnixDatagram::pair().unwrap();
+        let inner = SocketImpl::from_fd(b.into_raw_fd());
+        let mut socket = Socket::from_impl(inner, 0, true).unwrap();
+        for _ in 0..3 {
+            a.send(&[]).unwrap();
+        }
+
+        // peeking leaves the datagram queued for recv
+        let err = socket.peek_type().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let Err(err) = socket.recv() else {
+            panic!("received an empty datagram");
+        };
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let err = socket.recv_dump_bytes().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        let Err(err) = socket.recv_exact_sized() else {
+            panic!("received an empty datagram");
+        };
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_send_after_close() {
+        let Some((send, _, _recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        send.close().unwrap();
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 0), Payload::None);
+        let err = send.send(msg, &recv_addr).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+        assert_eq!(err.to_string(), "socket is closed");
+    }
+
+    #[test]
+    fn test_into_event_stream() {
+        let Some((send, _, recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        let (handle, events) = recv.into_event_stream();
+
+        for i in 0..2 {
+            let payload = [i; 4];
+            let msg = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&payload));
+            send.send(msg, &recv_addr).unwrap();
+        }
+        for i in 0..2 {
+            let event = events.recv().unwrap().unwrap();
+            assert_eq!(event.msg().payload(), &Payload::Data(&[i; 4]));
+        }
+
+        // the thread notices the dropped receiver with the next datagram
+        drop(events);
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 0), Payload::None);
+        send.send(msg, &recv_addr).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_set_seq() {
+        let Some((mut socket, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        assert_eq!(socket.seq(), 1);
+        socket.set_seq(500);
+        assert_eq!(socket.begin_request().seq(), 500);
+        assert_eq!(socket.seq(), 501);
+
+        socket.set_seq(u32::MAX);
+        assert_eq!(socket.begin_request().seq(), u32::MAX);
+        // wraps past 0
+        assert_eq!(socket.begin_request().seq(), 1);
+        socket.set_seq(0);
+        assert_eq!(socket.seq(), 1);
+
+        // the next header sent through a guard carries the value set
+        socket.set_seq(77);
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 0), Payload::None);
+        socket
+            .begin_request()
+            .send(msg.clone(), &recv_addr)
+            .unwrap();
+        let (_, messages) = recv.recv().unwrap();
+        assert_eq!(messages[0].header().msg_seq(), 77);
+
+        // a plain send leaves the header alone
+        socket.send(msg, &recv_addr).unwrap();
+        let (_, messages) = recv.recv().unwrap();
+        assert_eq!(messages[0].header().msg_seq(), 0);
+        assert_eq!(socket.seq(), 78);
+    }
+
+    #[test]
+    fn test_has_net_admin() {
+        let privileged = has_net_admin();
+        match require_net_admin() {
+            Ok(()) => assert!(privileged),
+            Err(e) => {
+                assert!(!privileged);
+                assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_owned_fd() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        let addr = socket.bind_auto().unwrap();
+        let fd = OwnedFd::try_from(socket).unwrap();
+
+        let socket = Socket::from_owned_fd(fd).unwrap();
+        assert_eq!(socket.protocol(), Protocol::Usersock as i32);
+        assert_eq!(socket.local_addr().unwrap(), addr);
+        assert!(socket.is_bound());
+
+        let udp = ::std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let Err(err) = Socket::from_owned_fd(OwnedFd::from(udp)) else {
+            panic!("adopted a UDP socket");
+        };
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let Some(closed) = try_socket() else {
+            return;
+        };
+        closed.close().unwrap();
+        let err = OwnedFd::try_from(closed).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotConnected);
+    }
+
+    #[test]
+    fn test_recv_with_nsid() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        match recv.set_listen_all_nsid(true) {
+            Err(ref e) if e.raw_os_error() == Some(EPERM) => {}
+            result => result.unwrap(),
+        }
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&[3; 4]));
+        send.send(msg, &recv_addr).unwrap();
+
+        let (addr, nsid, messages) = recv.recv_with_nsid().unwrap();
+        assert_eq!(addr, send_addr);
+        assert_eq!(nsid, -1);
+        assert_eq!(messages[0].payload(), &Payload::Data(&[3; 4]));
+    }
+
+    #[test]
+    fn test_priority() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert_eq!(socket.priority().unwrap(), 0);
+        socket.set_priority(4).unwrap();
+        assert_eq!(socket.priority().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_reuse_addr() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert!(!socket.reuse_addr().unwrap());
+        socket.set_reuse_addr(true).unwrap();
+        assert!(socket.reuse_addr().unwrap());
+        socket.set_reuse_addr(false).unwrap();
+        assert!(!socket.reuse_addr().unwrap());
+    }
+
+    #[test]
+    fn test_max_msg_size() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        socket
+            .inner
+            .setsockopt(SOL_SOCKET, SO_RCVBUF, MAX_NETLINK_MSG_SIZE as c_int)
+            .unwrap();
+        // doubled by the kernel, as for test_suggest_dump_buffer
+        assert!(socket.max_msg_size().unwrap() >= MAX_NETLINK_MSG_SIZE);
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert!(socket.max_send_size() <= socket.max_msg_size().unwrap());
+    }
+
+    #[test]
+    fn test_send_multicast() {
+        let Some(mut listener) = try_socket() else {
+            return;
+        };
+        listener.subscribe(&[3]).unwrap();
+        let Some(send) = try_socket() else {
+            return;
+        };
+        let send_addr = send.bind_auto().unwrap();
+
+        let bytes = [1, 2, 3, 4];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        // there is no kernel socket for usersock, so after the broadcast the
+        // unicast to pid 0 is refused
+        let err = send
+            .send(
+                Msg::new(shdr, Payload::Data(&bytes)),
+                &NetlinkAddr::multicast(3).unwrap(),
+            )
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+
+        let (addr, messages) = listener.recv().unwrap();
+        assert_eq!(addr.pid(), send_addr.pid());
+        assert_eq!(addr.groups(), 4);
+        assert_eq!(messages[0].payload(), &Payload::Data(&bytes));
+    }
+
+    // floods a listener with a tiny receive buffer on usersock group 5
+    fn overrun_pair() -> Option<(Socket, NetlinkAddr, Socket)> {
+        let listener = try_socket()?;
+        listener
+            .inner
+            .setsockopt(SOL_SOCKET, SO_RCVBUF, 1 as c_int)
+            .unwrap();
+        let listener_addr = listener.bind_auto().unwrap();
+        listener.add_membership(5).unwrap();
+        let send = try_socket()?;
+        send.bind_auto().unwrap();
+
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(4);
+        for _ in 0..64 {
+            let msg = Msg::new(shdr, Payload::Data(&[1, 1, 1, 1]));
+            // refused after the broadcast, see NetlinkAddr::multicast
+            let _ = send.send(msg, &NetlinkAddr::multicast(5).unwrap());
+        }
+        Some((listener, listener_addr, send))
+    }
+
+    #[test]
+    fn test_overrun_error() {
+        let Some((mut listener, _, _send)) = overrun_pair() else {
+            return;
+        };
+        let err = loop {
+            if let Err(e) = listener.recv() {
+                break e;
+            }
+        };
+        assert_eq!(err.raw_os_error(), Some(libc::ENOBUFS));
+    }
+
+    #[test]
+    fn test_on_overrun() {
+        let Some((mut listener, listener_addr, _send)) = overrun_pair() else {
+            return;
+        };
+        let called = Arc::new(Mutex::new(0));
+        let c = called.clone();
+        listener.on_overrun(Box::new(move |socket: &mut Socket| {
+            *c.lock().unwrap() += 1;
+            // stands in for re-issuing a dump
+            let mut shdr = NlMsgHeader::request();
+            shdr.data_length(4);
+            let msg = Msg::new(shdr, Payload::Data(&[9, 9, 9, 9]));
+            socket.send(msg, &listener_addr).unwrap();
+        }));
+
+        loop {
+            let (_, messages) = listener.recv().unwrap();
+            if messages[0].payload() == &Payload::Data(&[9, 9, 9, 9]) {
+                break;
+            }
+        }
+        assert_eq!(*called.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_on_overrun_exact_sized() {
+        let Some((mut listener, listener_addr, _send)) = overrun_pair() else {
+            return;
+        };
+        let called = Arc::new(Mutex::new(0));
+        let c = called.clone();
+        listener.on_overrun(Box::new(move |socket: &mut Socket| {
+            *c.lock().unwrap() += 1;
+            let mut shdr = NlMsgHeader::request();
+            shdr.data_length(4);
+            let msg = Msg::new(shdr, Payload::Data(&[9, 9, 9, 9]));
+            socket.send(msg, &listener_addr).unwrap();
+        }));
+
+        loop {
+            let (_, messages) = listener.recv_exact_sized().unwrap();
+            if messages[0].msg().payload() == &Payload::Data(&[9, 9, 9, 9]) {
+                break;
+            }
+        }
+        assert_eq!(*called.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_try_new() {
+        assert!(is_unavailable(&io::Error::from_raw_os_error(libc::EPERM)));
+        assert!(is_unavailable(&io::Error::from_raw_os_error(
+            libc::EAFNOSUPPORT
+        )));
+        assert!(!is_unavailable(&io::Error::from_raw_os_error(libc::EMFILE)));
+        // unknown protocol numbers are refused like missing protocols
+        assert!(Socket::try_new(31).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_send_queue_len() {
+        let Some((send, _, _recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        assert_eq!(send.send_queue_len().unwrap(), 0);
+        let msg = Msg::new(NlMsgHeader::request(), Payload::None);
+        send.send(msg, &recv_addr).unwrap();
+        assert_eq!(send.send_queue_len().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_protocol() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert_eq!(socket.protocol(), 2);
+        assert_eq!(
+            Protocol::from_i32(socket.protocol()),
+            Some(Protocol::Usersock)
+        );
+        assert_eq!(Protocol::from_i32(17), None);
+    }
+
+    #[test]
+    fn test_rx_ring_enable() {
+        let Some(mut socket) = try_socket() else {
+            return;
+        };
+        let err = socket.recv_ring().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = socket.enable_rx_ring(10, 16).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        // kernels before 4.7 may have been built with netlink mmap
+        match socket.enable_rx_ring(4096, 16) {
+            Ok(()) => {}
+            Err(e) => assert_eq!(e.kind(), ErrorKind::Unsupported),
+        }
+    }
+
+    #[test]
+    fn test_tx_ring_supported() {
+        let Some(mut socket) = try_socket() else {
+            return;
+        };
+        // both rings came with netlink mmap and went with it
+        if !socket.tx_ring_supported() {
+            let err = socket.enable_rx_ring(4096, 16).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::Unsupported);
+        }
+    }
+
+    #[test]
+    fn test_cloexec_by_default() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert!(socket.inner.cloexec().unwrap());
+        socket.set_cloexec(false).unwrap();
+        assert!(!socket.inner.cloexec().unwrap());
+    }
+
+    #[test]
+    fn test_new_with_flags() {
+        if try_socket().is_none() {
+            return;
+        }
+        let mut socket = Socket::new_with_flags(Protocol::Usersock, false, true).unwrap();
+        assert!(!socket.inner.cloexec().unwrap());
+        socket.bind_auto().unwrap();
+        let err = socket.recv().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn test_is_bound() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert!(!socket.is_bound());
+        let addr = socket.bind_auto().unwrap();
+        assert!(socket.is_bound());
+
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        let msg = Msg::new(NlMsgHeader::request(), Payload::None);
+        socket.send(msg, &addr).unwrap();
+        assert!(socket.is_bound());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        // RTNLGRP_LINK fits the bind mask, RTNLGRP_BRVLAN (33) does not
+        let Some(socket) = try_protocol(Protocol::Route) else {
+            return;
+        };
+        let addr = socket.subscribe(&[1, 33]).unwrap();
+        assert!(addr.pid() != 0);
+        // getsockname reports the first 32 groups as a mask
+        assert_eq!(addr.groups(), 1);
+        assert_eq!(socket.memberships().unwrap(), vec![1, 33]);
+        socket.drop_membership(33).unwrap();
+        assert_eq!(socket.memberships().unwrap(), vec![1]);
+
+        let Some(socket) = try_protocol(Protocol::Route) else {
+            return;
+        };
+        let err = socket.subscribe(&[1, 1000]).unwrap_err();
+        // EINVAL, with the group that was refused
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("group 1000"));
+        let inner = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<MembershipError>())
+            .unwrap();
+        assert_eq!(inner.group(), 1000);
+        assert_eq!(inner.os_error().raw_os_error(), Some(libc::EINVAL));
+    }
+
+    #[test]
+    fn test_reserve_buffer() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        assert_eq!(recv.buffer_capacity(), 4096);
+        recv.reserve_buffer(4096);
+        assert_eq!(recv.buffer_capacity(), 8192);
+
+        let bytes = vec![7u8; 6000];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(bytes.len() as u32);
+        send.send(Msg::new(shdr, Payload::Data(&bytes)), &recv_addr)
+            .unwrap();
+
+        let (_, messages) = recv.recv().unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload(), &Payload::Data(&bytes[..]));
+    }
+
+    #[test]
+    fn test_recv_exact_sized() {
+        let Some((send, send_addr, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        let bytes = vec![7u8; 6000];
+        let mut shdr = NlMsgHeader::request();
+        shdr.data_length(bytes.len() as u32);
+        send.send(Msg::new(shdr, Payload::Data(&bytes)), &recv_addr)
+            .unwrap();
+
+        let (addr, messages) = recv.recv_exact_sized().unwrap();
+        assert_eq!(addr, send_addr);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].msg().payload(), &Payload::Data(&bytes[..]));
+        assert_eq!(recv.buffer_capacity(), 4096);
+
+        // what recv_one left of a datagram is discarded
+        let mut hdr = NlMsgHeader::request();
+        hdr.data_length(4);
+        let first = Msg::new(hdr, Payload::Data(&[1; 4]));
+        let second = Msg::new(hdr, Payload::Data(&[2; 4]));
+        send.send_multi(vec![first, second], &recv_addr).unwrap();
+        assert!(recv.recv_one().unwrap().1.is_some());
+        let third = Msg::new(hdr, Payload::Data(&[3; 4]));
+        send.send(third.clone(), &recv_addr).unwrap();
+        send.send(third.clone(), &recv_addr).unwrap();
+        let (_, messages) = recv.recv_exact_sized().unwrap();
+        assert_eq!(messages[0].msg(), third);
+        assert_eq!(recv.recv_one().unwrap().1, Some(third));
+    }
+
+    #[test]
+    fn test_dump_iter() {
+        // RTM_GETLINK dump with an empty ifinfomsg
+        let ifinfomsg = [0u8; 16];
+        let mut hdr = NlMsgHeader::user_defined(18, ifinfomsg.len() as u32);
+        hdr.dump();
+        let request = Msg::new(hdr, Payload::Data(&ifinfomsg));
+
+        let Some(mut socket) = try_protocol(Protocol::Route) else {
+            return;
+        };
+        let links = socket
+            .dump_iter(request, &NetlinkAddr::new(0, 0))
+            .unwrap()
+            .collect::<io::Result<Vec<OwnedMsg>>>()
+            .unwrap();
+        assert!(!links.is_empty());
+        // RTM_NEWLINK for each link, the Done is not yielded
+        for link in &links {
+            assert_eq!(link.header().msg_type(), MsgType::UserDefined(16));
+        }
+    }
+
+    #[test]
+    fn test_take_error() {
+        let Some(socket) = try_socket() else {
+            return;
+        };
+        assert!(socket.take_error().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_validate_batch() {
+        let Some(mut send) = try_socket() else {
+            return;
+        };
+        send.set_max_send_size(64);
+
+        let bytes = [0u8; 5];
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 5), Payload::Data(&bytes));
+        // 21 bytes each, padded to 24
+        let batch = vec![msg.clone(), msg.clone()];
+        assert_eq!(send.validate_batch(&batch).unwrap(), 48);
+        assert_eq!(send.validate_batch(&[]).unwrap(), 0);
+
+        let batch = vec![msg.clone(), msg.clone(), msg];
+        let err = send.validate_batch(&batch).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = send.send_multi(batch, &NetlinkAddr::new(0, 0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_send_oversized() {
+        let Some(mut send) = try_socket() else {
+            return;
+        };
+        assert!(send.max_send_size() > 0);
+        send.set_max_send_size(32);
+
+        let bytes = [0u8; 24];
+        let hdr = NlMsgHeader::user_defined(20, 24);
+        let addr = NetlinkAddr::new(0, 0);
+        let err = send
+            .send(Msg::new(hdr, Payload::Data(&bytes)), &addr)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let small = Msg::new(NlMsgHeader::user_defined(20, 8), Payload::Data(&bytes[..8]));
+        let err = send
+            .send_multi(vec![small.clone(), small], &addr)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    fn ext_ack_error(errno: i32, attrs: &AttrBuilder) -> Vec<u8> {
+        let mut req = NlMsgHeader::user_defined(20, 8);
+        req.seq(3).pid(9);
+
+        let mut body = vec![];
+        body.write_i32::<NativeEndian>(errno).unwrap();
+        let _ = body.write(req.bytes()).unwrap();
+        body.extend_from_slice(attrs.bytes());
+
+        let mut hdr = NlMsgHeader::error();
+        hdr.data_length(body.len() as u32).capped().ack_tlvs();
+        let mut bytes: Vec<u8> = hdr.bytes().into();
+        bytes.append(&mut body);
+        bytes
+    }
+
+    #[test]
+    fn test_msg_decode_ext_ack_cookie() {
+        let mut attrs = AttrBuilder::new();
+        attrs
+            .put_str(NLMSGERR_ATTR_MSG, "Invalid handle")
+            .put_u32(NLMSGERR_ATTR_OFFS, 20)
+            .put(NLMSGERR_ATTR_COOKIE, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        let bytes = ext_ack_error(-22, &attrs);
+
+        let (msg, n) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(n, bytes.len());
+        let ext_ack = msg.ext_ack().unwrap();
+        assert_eq!(ext_ack.message(), Some("Invalid handle"));
+        assert_eq!(ext_ack.offset(), Some(20));
+        assert_eq!(ext_ack.cookie(), Some(&[1, 2, 3, 4, 5, 6, 7, 8][..]));
+    }
+
+    #[test]
+    fn test_msg_decode_ack_with_ext_ack() {
+        // a successful request can still carry a warning
+        let mut attrs = AttrBuilder::new();
+        attrs.put_str(NLMSGERR_ATTR_MSG, "Deprecated attribute");
+        let bytes = ext_ack_error(0, &attrs);
+
+        let (msg, n) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(n, bytes.len());
+        match *msg.payload() {
+            Payload::Ack(req) => assert_eq!(req.msg_length(), 24),
+            _ => panic!("msg is not Ack enum"),
+        }
+        assert!(msg.as_os_error().is_none());
+        let ext_ack = msg.ext_ack().unwrap();
+        assert_eq!(ext_ack.message(), Some("Deprecated attribute"));
+        assert_eq!(ext_ack.offset(), None);
+    }
+
+    #[test]
+    fn test_msg_decode_ext_ack_without_cookie() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_str(NLMSGERR_ATTR_MSG, "Invalid handle");
+        let bytes = ext_ack_error(-22, &attrs);
+
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        let ext_ack = msg.ext_ack().unwrap();
+        assert_eq!(ext_ack.message(), Some("Invalid handle"));
+        assert_eq!(ext_ack.cookie(), None);
+    }
+
+    #[test]
+    fn test_msg_as_os_error() {
+        let request = NlMsgHeader::request();
+        let msg = Msg::new(NlMsgHeader::error(), Payload::Err(-libc::EPERM, request));
+        let err = msg.as_os_error().unwrap();
+        assert_eq!(err.raw_os_error(), Some(libc::EPERM));
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        let ack = Msg::new(NlMsgHeader::error(), Payload::Ack(request));
+        assert!(ack.as_os_error().is_none());
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let mut hdr = NlMsgHeader::request();
+        let err = Msg::new(hdr, Payload::None).validate_request().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        hdr.seq(1);
+        Msg::new(hdr, Payload::None).validate_request().unwrap();
+        // only requests are checked
+        Msg::new(NlMsgHeader::done(), Payload::None)
+            .validate_request()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_msg_with_attrs() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7).put_str(2, "eth0");
+        let msg = Msg::with_attrs(MsgType::UserDefined(20), 0x0301, 4, 9, attrs.bytes());
+        let bytes = msg.bytes().unwrap();
+
+        let (decoded, _) = Msg::from_bytes(&bytes).unwrap();
+        let hdr = decoded.header();
+        assert_eq!(hdr.msg_type(), MsgType::UserDefined(20));
+        assert_eq!(hdr.msg_flags(), 0x0301);
+        assert_eq!(hdr.msg_seq(), 4);
+        assert_eq!(hdr.msg_pid(), 9);
+        assert_eq!(hdr.msg_length() as usize, 16 + attrs.bytes().len());
+
+        let body = match *decoded.payload() {
+            Payload::Data(b) => b,
+            _ => panic!("msg is not Data enum"),
+        };
+        let parsed: Vec<NlAttr> = AttrIter::new(body).collect::<io::Result<_>>().unwrap();
+        assert_eq!(parsed[0].as_u32().unwrap(), 7);
+        assert_eq!(parsed[1].as_str().unwrap(), "eth0");
+    }
+
+    #[test]
+    fn test_error_reply_cap_ack() {
+        let mut hdr = NlMsgHeader::user_defined(20, 8);
+        hdr.seq(5).pid(77);
+        let request = Msg::new(hdr, Payload::Data(&[1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let capped = Msg::error(&request, libc::EINVAL, true).unwrap();
+        assert_eq!(capped.bytes().len(), 16 + 4 + 16);
+        let reply = capped.msg();
+        assert!(reply.header().is_capped());
+        assert_eq!(reply.header().msg_seq(), 5);
+        assert_eq!(reply.header().msg_pid(), 77);
+        assert_eq!(reply.payload(), &Payload::Err(-libc::EINVAL, hdr));
+
+        let uncapped = Msg::error(&request, libc::EINVAL, false).unwrap();
+        assert_eq!(uncapped.bytes().len(), 16 + 4 + 16 + 8);
+        assert!(!uncapped.header().is_capped());
+        assert_eq!(uncapped.msg().payload(), &Payload::Err(-libc::EINVAL, hdr));
+        assert_eq!(&uncapped.bytes()[36..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let ack = Msg::ack(&request).unwrap();
+        assert!(ack.header().is_capped());
+        assert_eq!(ack.msg().payload(), &Payload::Ack(hdr));
+    }
+
+    #[test]
+    fn test_error_layout() {
+        let mut hdr = NlMsgHeader::user_defined(20, 8);
+        hdr.seq(5);
+        let request = Msg::new(hdr, Payload::Data(&[1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let full = Msg::error(&request, libc::EPERM, false).unwrap();
+        assert_eq!(full.msg().error_layout(), Some(ErrorLayout::Full));
+        assert_eq!(full.msg().payload(), &Payload::Err(-libc::EPERM, hdr));
+
+        let capped = Msg::error(&request, libc::EPERM, true).unwrap();
+        assert_eq!(capped.msg().error_layout(), Some(ErrorLayout::Capped));
+        assert_eq!(
+            Msg::ack(&request).unwrap().msg().error_layout(),
+            Some(ErrorLayout::Capped)
+        );
+
+        // capped without NLM_F_CAPPED, as older kernels send it
+        let mut bytes = capped.bytes().to_vec();
+        let mut ehdr = NlMsgHeader::error();
+        ehdr.seq(5);
+        bytes[..16].copy_from_slice(ehdr.bytes());
+        let (msg, n) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(n, bytes.len());
+        assert!(!msg.header().is_capped());
+        assert_eq!(msg.payload(), &Payload::Err(-libc::EPERM, hdr));
+        assert_eq!(msg.error_layout(), Some(ErrorLayout::Capped));
+
+        assert_eq!(request.error_layout(), None);
+    }
+
+    #[test]
+    fn test_frame_reader() {
+        let first = Msg::new(NlMsgHeader::user_defined(20, 5), Payload::Data(&[1; 5]));
+        let second = Msg::new(NlMsgHeader::user_defined(21, 3), Payload::Data(&[2; 3]));
+        let (a, b) = (first.bytes().unwrap(), second.bytes().unwrap());
+        let stream = concat_aligned(&[&a, &b]);
+
+        let msgs: Vec<OwnedMsg> = FrameReader::new(Cursor::new(&stream))
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].msg(), first);
+        assert_eq!(msgs[1].msg(), second);
+
+        // cut inside the second message
+        let mut frames = FrameReader::new(Cursor::new(&stream[..30]));
+        assert!(frames.next().unwrap().is_ok());
+        let err = frames.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+        assert!(frames.next().is_none());
+    }
+
+    #[test]
+    fn test_concat_aligned() {
+        let first = Msg::new(NlMsgHeader::user_defined(20, 5), Payload::Data(&[1; 5]));
+        let second = Msg::new(NlMsgHeader::user_defined(21, 3), Payload::Data(&[2; 3]));
+        let (a, b) = (first.bytes().unwrap(), second.bytes().unwrap());
+
+        let bytes = concat_aligned(&[&a, &b]);
+        // 21 bytes padded to 24, then 19 padded to 20
+        assert_eq!(bytes.len(), 24 + 20);
+        assert_eq!(&bytes[21..24], &[0, 0, 0]);
+        assert_eq!(&bytes[24..43], &b[..]);
+
+        let msgs: Vec<Msg> = MsgIter::new(&bytes).collect::<io::Result<_>>().unwrap();
+        assert_eq!(msgs, vec![first, second]);
+    }
+
+    #[test]
+    fn test_msg_length_error_context() {
+        let mut hdr = NlMsgHeader::user_defined(20, 48);
+        hdr.seq(1);
+        let err = Msg::from_bytes(hdr.bytes()).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        let text = err.to_string();
+        assert!(text.contains("length 64 with 16 bytes"), "{}", text);
+        // nlmsg_len as it is on the wire
+        assert!(text.contains(&hex_prefix(&64u32.to_ne_bytes())), "{}", text);
+    }
+
+    #[test]
+    fn test_dump_request() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(3, 1);
+        let msg = Msg::dump_request(MsgType::UserDefined(18), attrs.bytes());
+        let bytes = msg.bytes().unwrap();
+        assert_eq!(bytes.len(), 16 + 8);
+
+        let (decoded, _) = Msg::from_bytes(&bytes).unwrap();
+        let hdr = decoded.header();
+        assert_eq!(hdr.msg_type(), MsgType::UserDefined(18));
+        // NLM_F_REQUEST | NLM_F_ROOT | NLM_F_MATCH
+        assert_eq!(hdr.msg_flags(), 0x0301);
+        assert_eq!(hdr.msg_length(), 24);
+    }
+
+    #[test]
+    fn test_payload_errno() {
+        let payload = Payload::Err(-1, NlMsgHeader::request());
+        assert_eq!(payload.errno(), Some(libc::EPERM));
+        assert_eq!(Payload::Ack(NlMsgHeader::request()).errno(), None);
+        assert_eq!(Payload::None.errno(), None);
+    }
+
+    #[test]
+    fn test_payload_from_io_error() {
+        let mut request = NlMsgHeader::request();
+        request.seq(4);
+        let err = io::Error::from_raw_os_error(libc::EACCES);
+        let payload = Payload::from_io_error(&err, request);
+        assert_eq!(payload, Payload::Err(-libc::EACCES, request));
+
+        let msg = Msg::new(NlMsgHeader::error(), payload);
+        let bytes = msg.bytes().unwrap();
+        let (decoded, _) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            decoded.as_os_error().unwrap().raw_os_error(),
+            Some(libc::EACCES)
+        );
+
+        let err = io::Error::other("no errno");
+        let payload = Payload::from_io_error(&err, request);
+        assert_eq!(payload, Payload::Err(-libc::EIO, request));
+    }
+
+    #[test]
+    fn test_msg_payload_bytes() {
+        let mut attrs = AttrBuilder::new();
+        attrs.put_u32(1, 7).put_str(2, "eth0");
+        let msg = Msg::with_attrs(MsgType::UserDefined(20), 0, 1, 0, attrs.bytes());
+        let bytes = msg.bytes().unwrap();
+
+        let (decoded, _) = Msg::from_bytes(&bytes).unwrap();
+        let payload = decoded.payload_bytes().unwrap();
+        assert_eq!(payload, &bytes[nlmsg_header_length()..]);
+        let attr = AttrIter::new(payload).next().unwrap().unwrap();
+        assert_eq!(attr.as_u32().unwrap(), 7);
+
+        let done = Msg::new(NlMsgHeader::done(), Payload::None);
+        let err = done.payload_bytes().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_msg_wire_len() {
+        let bytes = [0, 1, 2, 3, 4];
+        let msg = Msg::new(NlMsgHeader::user_defined(20, 5), Payload::Data(&bytes));
+        // 16 byte header and 5 bytes of data, padded to 24
+        assert_eq!(msg.bytes().unwrap().len(), 21);
+        assert_eq!(msg.wire_len(), 24);
+
+        let aligned = Msg::new(NlMsgHeader::user_defined(20, 4), Payload::Data(&bytes[..4]));
+        assert_eq!(aligned.wire_len(), 20);
+
+        let done = Msg::new(NlMsgHeader::done(), Payload::None);
+        assert_eq!(done.wire_len(), 16);
+
+        let ack = Msg::new(NlMsgHeader::error(), Payload::Ack(NlMsgHeader::request()));
+        assert_eq!(ack.wire_len(), ack.bytes().unwrap().len());
+    }
+
+    #[test]
+    fn test_recv_dump_bytes() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        // an odd length message ends the first datagram unpadded
+        let odd = [1, 2, 3, 4, 5];
+        let mut hdr = NlMsgHeader::user_defined(20, 5);
+        hdr.multipart().seq(1);
+        send.send(Msg::new(hdr, Payload::Data(&odd)), &recv_addr)
+            .unwrap();
+
+        let even = [6, 7, 8, 9];
+        let mut hdr2 = NlMsgHeader::user_defined(21, 4);
+        hdr2.multipart().seq(1);
+        let done = Msg::new(NlMsgHeader::done(), Payload::None);
+        send.send_multi(vec![Msg::new(hdr2, Payload::Data(&even)), done], &recv_addr)
+            .unwrap();
+
+        let dump = recv.recv_dump_bytes().unwrap();
+        assert_eq!(dump.len(), 24 + 20);
+
+        let msgs: Vec<Msg> = MsgIter::new(&dump).collect::<io::Result<_>>().unwrap();
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].header(), hdr);
+        assert_eq!(msgs[0].payload(), &Payload::Data(&odd));
+        assert_eq!(msgs[1].header(), hdr2);
+        assert_eq!(msgs[1].payload(), &Payload::Data(&even));
+    }
+
+    #[test]
+    fn test_recv_dump_bytes_interrupted() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+
+        // a dump cut short by EINTR
+        let mut hdr = NlMsgHeader::user_defined(20, 4);
+        hdr.multipart().seq(1);
+        let mut donehdr = NlMsgHeader::done();
+        donehdr.data_length(4).seq(1);
+        let done = Msg::new(donehdr, Payload::Done(-libc::EINTR));
+        send.send_multi(
+            vec![Msg::new(hdr, Payload::Data(&[1, 2, 3, 4])), done],
+            &recv_addr,
+        )
+        .unwrap();
+
+        let err = recv.recv_dump_bytes().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EINTR));
+    }
+
+    #[test]
+    fn test_recv_dump_bytes_overrun() {
+        let Some((send, _, mut recv, recv_addr)) = usersock_pair() else {
+            return;
+        };
+        let traced = Arc::new(Mutex::new(vec![]));
+        let log = traced.clone();
+        recv.set_trace(Box::new(move |_, m| {
+            log.lock().unwrap().push(m.header().msg_type())
+        }));
+
+        let mut hdr = NlMsgHeader::user_defined(20, 4);
+        hdr.multipart().seq(1);
+        let overrun = Msg::new(
+            NlMsgHeader::user_defined(MsgType::Overrun.into(), 0),
+            Payload::None,
+        );
+        send.send_multi(
+            vec![Msg::new(hdr, Payload::Data(&[1, 2, 3, 4])), overrun],
+            &recv_addr,
+        )
+        .unwrap();
+
+        let err = recv.recv_dump_bytes().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+        assert_eq!(
+            *traced.lock().unwrap(),
+            vec![MsgType::UserDefined(20), MsgType::Overrun]
+        );
+    }
+
+    #[test]
+    fn test_done_status_decode() {
+        let mut hdr = NlMsgHeader::done();
+        hdr.data_length(4);
+        let bytes = Msg::new(hdr, Payload::Done(0)).bytes().unwrap();
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(msg.payload(), &Payload::Done(0));
+        assert_eq!(msg.payload().errno(), None);
+
+        let bytes = Msg::new(hdr, Payload::Done(-libc::EINTR)).bytes().unwrap();
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(msg.payload().errno(), Some(libc::EINTR));
+
+        // a Done without a status
+        let bytes = Msg::new(NlMsgHeader::done(), Payload::None)
+            .bytes()
+            .unwrap();
+        let (msg, _) = Msg::from_bytes(&bytes).unwrap();
+        assert_eq!(msg.payload(), &Payload::None);
+    }
+
+    #[test]
+    fn test_payload_decode() {
+        let bytes = [0, 1, 2, 3, 4, 5];
+        let (payload, n) = Payload::data(&bytes, bytes.len()).unwrap();
+        assert_eq!(n, bytes.len());
+
+        if let Payload::Data(b) = payload {
+            assert_eq!(b, &bytes);
+        } else {
+            panic!("payload is not Data enum");
+        }
+    }
+
+    #[test]
+    fn test_payload_decode_with_err() {
+        let mut bytes = vec![];
+        bytes.write_u32::<NativeEndian>(1).unwrap();
+
+        let mut hdr = NlMsgHeader::request();
+        hdr.data_length(4).pid(9).seq(1).dump();
+
+        // embedded header, written field by field in native byte order
+        bytes.write_u32::<NativeEndian>(20).unwrap();
+        bytes.write_u16::<NativeEndian>(0).unwrap();
+        bytes.write_u16::<NativeEndian>(0x0301).unwrap();
+        bytes.write_u32::<NativeEndian>(1).unwrap();
+        bytes.write_u32::<NativeEndian>(9).unwrap();
+
+        let (p, ext_ack, n) =
+            Payload::nlmsg_error(&bytes, &NlMsgHeader::error(), &SocketConfig::new()).unwrap();
+
+        assert_eq!(n, bytes.len());
+        assert!(ext_ack.is_none());
+        if let Payload::Err(_, h) = p {
+            assert_eq!(h, hdr);
+        } else {
+            panic!("payload is not Err enum");
+        }
+    }
+
+    #[test]
+    fn test_payload_decode_with_ack() {
+        let mut bytes = vec![];
+        bytes.write_u32::<NativeEndian>(0).unwrap();
+
+        let mut hdr = NlMsgHeader::request();
+        hdr.data_length(4).pid(9).seq(1).dump();
+
+        let _ = bytes.write(hdr.bytes()).unwrap();
+
+        let (p, ext_ack, n) =
+            Payload::nlmsg_error(&bytes, &NlMsgHeader::error(), &SocketConfig::new()).unwrap();
+
+        assert_eq!(n, bytes.len());
+        assert!(ext_ack.is_none());
+        if let Payload::Ack(h) = p {
+            assert_eq!(h, hdr);
+        } else {
             panic!("payload is not Ack enum");
         }
+
+        let err = Payload::nlmsg_error(&bytes[..2], &NlMsgHeader::error(), &SocketConfig::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
     }
 
     #[test]
     fn test_msg_decode() {
-        // Little endian only right now
         let mut hdr = NlMsgHeader::request();
         hdr.data_length(4).pid(9).seq(1).dump();
         let hdr_bytes = hdr.bytes();
@@ -406,5 +3844,6 @@ mod tests {
         } else {
             panic!("msg is not Err enum");
         }
+        assert!(msg.ext_ack().is_none());
     }
 }
@@ -4,8 +4,12 @@ use std::fmt;
 use std::io::{self, Cursor, ErrorKind};
 use std::mem::size_of;
 use std::slice::from_raw_parts;
+use Protocol;
 
-#[derive(Clone, Copy, Debug)]
+/// Types below are reserved for control messages, from linux/netlink.h
+pub const NLMSG_MIN_TYPE: u16 = 0x10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum MsgType {
     /// Request
     Request,
@@ -17,27 +21,120 @@ pub enum MsgType {
     Done,
     /// Data lost
     Overrun,
-    /// minimum type, below 10 for reserved control messages
-    MinType,
-    /// User defined type, passed to the user
+    /// User defined type, passed to the user. Types from `NLMSG_MIN_TYPE`
+    /// on are protocol specific.
     UserDefined(u16),
 }
 
-impl Into<u16> for MsgType {
-    fn into(self) -> u16 {
+impl From<MsgType> for u16 {
+    fn from(t: MsgType) -> u16 {
         use self::MsgType::*;
-        match self {
+        match t {
             Request => 0,
             Noop => 1,
             Error => 2,
             Done => 3,
             Overrun => 4,
-            MinType => 10,
             UserDefined(i) => i,
         }
     }
 }
 
+impl MsgType {
+    /// Whether the type is in the range reserved for control messages
+    pub fn is_control(&self) -> bool {
+        u16::from(*self) < NLMSG_MIN_TYPE
+    }
+
+    /// Name of the message type in the context of `protocol`, e.g. 16 is
+    /// `RTM_NEWLINK` under `Protocol::Route`
+    pub fn name(&self, protocol: Protocol) -> &'static str {
+        match *self {
+            MsgType::Request => "REQUEST",
+            MsgType::Noop => "NLMSG_NOOP",
+            MsgType::Error => "NLMSG_ERROR",
+            MsgType::Done => "NLMSG_DONE",
+            MsgType::Overrun => "NLMSG_OVERRUN",
+            MsgType::UserDefined(t) => match protocol {
+                Protocol::Route => rtm_name(t),
+                Protocol::Generic => genl_name(t),
+                _ => "UNKNOWN",
+            },
+        }
+    }
+}
+
+// from linux/rtnetlink.h
+fn rtm_name(t: u16) -> &'static str {
+    match t {
+        16 => "RTM_NEWLINK",
+        17 => "RTM_DELLINK",
+        18 => "RTM_GETLINK",
+        19 => "RTM_SETLINK",
+        20 => "RTM_NEWADDR",
+        21 => "RTM_DELADDR",
+        22 => "RTM_GETADDR",
+        24 => "RTM_NEWROUTE",
+        25 => "RTM_DELROUTE",
+        26 => "RTM_GETROUTE",
+        28 => "RTM_NEWNEIGH",
+        29 => "RTM_DELNEIGH",
+        30 => "RTM_GETNEIGH",
+        32 => "RTM_NEWRULE",
+        33 => "RTM_DELRULE",
+        34 => "RTM_GETRULE",
+        36 => "RTM_NEWQDISC",
+        37 => "RTM_DELQDISC",
+        38 => "RTM_GETQDISC",
+        40 => "RTM_NEWTCLASS",
+        41 => "RTM_DELTCLASS",
+        42 => "RTM_GETTCLASS",
+        44 => "RTM_NEWTFILTER",
+        45 => "RTM_DELTFILTER",
+        46 => "RTM_GETTFILTER",
+        48 => "RTM_NEWACTION",
+        49 => "RTM_DELACTION",
+        50 => "RTM_GETACTION",
+        52 => "RTM_NEWPREFIX",
+        58 => "RTM_GETMULTICAST",
+        62 => "RTM_GETANYCAST",
+        64 => "RTM_NEWNEIGHTBL",
+        66 => "RTM_GETNEIGHTBL",
+        67 => "RTM_SETNEIGHTBL",
+        68 => "RTM_NEWNDUSEROPT",
+        72 => "RTM_NEWADDRLABEL",
+        73 => "RTM_DELADDRLABEL",
+        74 => "RTM_GETADDRLABEL",
+        78 => "RTM_GETDCB",
+        79 => "RTM_SETDCB",
+        80 => "RTM_NEWNETCONF",
+        81 => "RTM_DELNETCONF",
+        82 => "RTM_GETNETCONF",
+        84 => "RTM_NEWMDB",
+        85 => "RTM_DELMDB",
+        86 => "RTM_GETMDB",
+        88 => "RTM_NEWNSID",
+        89 => "RTM_DELNSID",
+        90 => "RTM_GETNSID",
+        92 => "RTM_NEWSTATS",
+        94 => "RTM_GETSTATS",
+        104 => "RTM_NEWNEXTHOP",
+        105 => "RTM_DELNEXTHOP",
+        106 => "RTM_GETNEXTHOP",
+        _ => "UNKNOWN",
+    }
+}
+
+// generic netlink families are assigned at runtime, only these are fixed
+fn genl_name(t: u16) -> &'static str {
+    match t {
+        16 => "GENL_ID_CTRL",
+        17 => "GENL_ID_VFS_DQUOT",
+        18 => "GENL_ID_PMCRAID",
+        _ => "UNKNOWN",
+    }
+}
+
 impl From<u16> for MsgType {
     fn from(t: u16) -> MsgType {
         use self::MsgType::*;
@@ -47,7 +144,7 @@ impl From<u16> for MsgType {
             2 => Error,
             3 => Done,
             4 => Overrun,
-            10 => MinType,
+            // the rest of the reserved range has no meaning assigned
             i => UserDefined(i),
         }
     }
@@ -63,16 +160,22 @@ enum Flags {
     Ack,
     /// Echo this request
     Echo,
+    /// Dump was inconsistent due to sequence change
+    DumpIntr,
+    /// Dump was filtered as requested
+    DumpFiltered,
 }
 
-impl Into<u16> for Flags {
-    fn into(self) -> u16 {
+impl From<Flags> for u16 {
+    fn from(f: Flags) -> u16 {
         use self::Flags::*;
-        match self {
+        match f {
             Request => 1,
             Multi => 2,
             Ack => 4,
             Echo => 8,
+            DumpIntr => 0x10,
+            DumpFiltered => 0x20,
         }
     }
 }
@@ -90,10 +193,10 @@ enum GetFlags {
     Dump,
 }
 
-impl Into<u16> for GetFlags {
-    fn into(self) -> u16 {
+impl From<GetFlags> for u16 {
+    fn from(f: GetFlags) -> u16 {
         use self::GetFlags::*;
-        match self {
+        match f {
             Root => 0x100,
             Match => 0x200,
             Atomic => 0x400,
@@ -115,10 +218,10 @@ enum NewFlags {
     Append,
 }
 
-impl Into<u16> for NewFlags {
-    fn into(self) -> u16 {
+impl From<NewFlags> for u16 {
+    fn from(f: NewFlags) -> u16 {
         use self::NewFlags::*;
-        match self {
+        match f {
             Replace => 0x100,
             Excl => 0x200,
             Create => 0x400,
@@ -127,6 +230,25 @@ impl Into<u16> for NewFlags {
     }
 }
 
+/// Flags for ACK message
+#[derive(Clone, Copy, Debug)]
+enum AckFlags {
+    /// request was capped
+    Capped,
+    /// extended ACK TVLs were included
+    AckTlvs,
+}
+
+impl From<AckFlags> for u16 {
+    fn from(f: AckFlags) -> u16 {
+        use self::AckFlags::*;
+        match f {
+            Capped => 0x100,
+            AckTlvs => 0x200,
+        }
+    }
+}
+
 // HEADER FORMAT
 // __u32 nlmsg_len;    /* Length of message including header. */
 // __u16 nlmsg_type;   /* Type of message content. */
@@ -198,6 +320,8 @@ impl NlMsgHeader {
         }
     }
 
+    /// The `NLMSG_DONE` ending a dump. Like the kernel's, it carries
+    /// `NLM_F_MULTI` as part of the multipart reply it terminates.
     pub fn done() -> NlMsgHeader {
         NlMsgHeader {
             msg_length: nlmsg_header_length() as u32,
@@ -218,7 +342,15 @@ impl NlMsgHeader {
         }
     }
 
+    /// Reads the header at the start of `bytes`. Too few bytes or a length
+    /// below the header size fail with `InvalidData`.
     pub fn from_bytes(bytes: &[u8]) -> io::Result<(NlMsgHeader, usize)> {
+        if bytes.len() < size_of::<NlMsgHeader>() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} bytes are too few for a msg header", bytes.len()),
+            ));
+        }
         let mut cursor = Cursor::new(bytes);
         let len = cursor.read_u32::<NativeEndian>()?;
         let nl_type = cursor.read_u16::<NativeEndian>()?;
@@ -228,8 +360,8 @@ impl NlMsgHeader {
 
         if len < nlmsg_header_length() as u32 {
             Err(io::Error::new(
-                ErrorKind::InvalidInput,
-                "length smaller than msg header size",
+                ErrorKind::InvalidData,
+                format!("length {} smaller than msg header size", len),
             ))
         } else {
             Ok((
@@ -261,6 +393,24 @@ impl NlMsgHeader {
         self.msg_length
     }
 
+    /// All NLM_F_* flags set on the header
+    pub fn msg_flags(&self) -> u16 {
+        self.flags
+    }
+
+    pub fn msg_seq(&self) -> u32 {
+        self.seq
+    }
+
+    pub fn msg_pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn is_request(&self) -> bool {
+        let request: u16 = Flags::Request.into();
+        self.flags & request != 0
+    }
+
     /// Set message length
     pub fn data_length(&mut self, len: u32) -> &mut NlMsgHeader {
         self.msg_length = nlmsg_length(len as usize) as u32;
@@ -281,13 +431,28 @@ impl NlMsgHeader {
         self
     }
 
-    /// Echo message
+    /// Ask the kernel to echo the request back; for a change, the object
+    /// as applied is sent to the requester along with the ACK, see
+    /// `Socket::execute`
     pub fn echo(&mut self) -> &mut NlMsgHeader {
         let echo: u16 = Flags::Echo.into();
         self.flags |= echo;
         self
     }
 
+    /// Replace all NLM_F_* flags
+    pub fn flags(&mut self, flags: u16) -> &mut NlMsgHeader {
+        self.flags = flags;
+        self
+    }
+
+    /// Set the message type to any value, e.g. a protocol type this crate
+    /// has no constant for
+    pub fn raw_type(&mut self, ty: u16) -> &mut NlMsgHeader {
+        self.nl_type = ty;
+        self
+    }
+
     /// Set sequence number
     pub fn seq(&mut self, n: u32) -> &mut NlMsgHeader {
         self.seq = n;
@@ -336,12 +501,17 @@ impl NlMsgHeader {
     }
 
     /// return all matching
-    pub fn match_provided(&mut self) -> &mut NlMsgHeader {
+    pub fn match_(&mut self) -> &mut NlMsgHeader {
         let matc: u16 = GetFlags::Match.into();
         self.flags |= matc;
         self
     }
 
+    /// Same as `match_`
+    pub fn match_provided(&mut self) -> &mut NlMsgHeader {
+        self.match_()
+    }
+
     /// atomic GET
     pub fn atomic(&mut self) -> &mut NlMsgHeader {
         let atomic: u16 = GetFlags::Atomic.into();
@@ -355,6 +525,69 @@ impl NlMsgHeader {
         self.flags |= dump;
         self
     }
+
+    /// Error reply embeds only the header of the request
+    pub fn capped(&mut self) -> &mut NlMsgHeader {
+        let capped: u16 = AckFlags::Capped.into();
+        self.flags |= capped;
+        self
+    }
+
+    /// Error reply carries extended ACK attributes
+    pub fn ack_tlvs(&mut self) -> &mut NlMsgHeader {
+        let tlvs: u16 = AckFlags::AckTlvs.into();
+        self.flags |= tlvs;
+        self
+    }
+
+    pub fn is_capped(&self) -> bool {
+        let capped: u16 = AckFlags::Capped.into();
+        self.flags & capped != 0
+    }
+
+    pub fn has_ack_tlvs(&self) -> bool {
+        let tlvs: u16 = AckFlags::AckTlvs.into();
+        self.flags & tlvs != 0
+    }
+
+    /// The set flags by name, e.g. `REQUEST|DUMP`. Bits above `ECHO` mean
+    /// different things per message, so they are named as GET modifiers on
+    /// requests and as ACK flags on errors; anything else is shown in hex.
+    pub fn flags_description(&self) -> String {
+        let mut names = vec![];
+        let mut rest = self.flags;
+        let mut take = |bits: u16, name: &'static str| {
+            if bits != 0 && rest & bits == bits {
+                names.push(name);
+                rest &= !bits;
+            }
+        };
+
+        take(Flags::Request.into(), "REQUEST");
+        take(Flags::Multi.into(), "MULTI");
+        take(Flags::Ack.into(), "ACK");
+        take(Flags::Echo.into(), "ECHO");
+        take(Flags::DumpIntr.into(), "DUMP_INTR");
+        take(Flags::DumpFiltered.into(), "DUMP_FILTERED");
+        if self.msg_type() == MsgType::Error {
+            take(AckFlags::Capped.into(), "CAPPED");
+            take(AckFlags::AckTlvs.into(), "ACK_TLVS");
+        } else if self.is_request() {
+            take(GetFlags::Dump.into(), "DUMP");
+            take(GetFlags::Root.into(), "ROOT");
+            take(GetFlags::Match.into(), "MATCH");
+            take(GetFlags::Atomic.into(), "ATOMIC");
+        }
+
+        let mut description = names.join("|");
+        if rest != 0 || names.is_empty() {
+            if !names.is_empty() {
+                description.push('|');
+            }
+            description.push_str(&format!("{:#x}", rest));
+        }
+        description
+    }
 }
 
 /*
@@ -378,6 +611,10 @@ http://linux.die.net/include/linux/netlink.h
 #define NLM_F_CREATE    0x400   /* Create, if it does not exist */
 #define NLM_F_APPEND    0x800   /* Add to end of list       */
 
+/* Flags for ACK message */
+#define NLM_F_CAPPED    0x100   /* request was capped */
+#define NLM_F_ACK_TLVS  0x200   /* extended ACK TVLs were included */
+
 /*
    4.4BSD ADD       NLM_F_CREATE|NLM_F_EXCL
    4.4BSD CHANGE    NLM_F_REPLACE
@@ -398,21 +635,136 @@ http://linux.die.net/include/linux/netlink.h
 #[cfg(test)]
 mod tests {
     use super::*;
+    use byteorder::WriteBytesExt;
+
+    /// Encodes a header field by field with the crate's byte order
+    fn header_bytes(len: u32, nl_type: u16, flags: u16, seq: u32, pid: u32) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.write_u32::<NativeEndian>(len).unwrap();
+        bytes.write_u16::<NativeEndian>(nl_type).unwrap();
+        bytes.write_u16::<NativeEndian>(flags).unwrap();
+        bytes.write_u32::<NativeEndian>(seq).unwrap();
+        bytes.write_u32::<NativeEndian>(pid).unwrap();
+        bytes
+    }
 
     #[test]
     fn test_encoding() {
-        // Little endian only right now
-        let expected = [20, 0, 0, 0, 0, 0, 1, 3, 1, 0, 0, 0, 9, 0, 0, 0];
+        let expected = header_bytes(20, 0, 0x0301, 1, 9);
         let mut hdr = NlMsgHeader::request();
         let bytes = hdr.data_length(4).pid(9).seq(1).dump().bytes();
 
-        assert_eq!(bytes, expected);
+        assert_eq!(bytes, &expected[..]);
+    }
+
+    #[test]
+    fn test_malformed_header() {
+        let short = header_bytes(16, 0, 0, 0, 0);
+        let err = NlMsgHeader::from_bytes(&short[..8]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let err = NlMsgHeader::from_bytes(&header_bytes(8, 0, 0, 0, 0)).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_raw_type() {
+        let mut hdr = NlMsgHeader::request();
+        hdr.raw_type(0x1234);
+        assert_eq!(hdr.bytes(), &header_bytes(16, 0x1234, 0x1, 0, 0)[..]);
+
+        let (decoded, _) = NlMsgHeader::from_bytes(hdr.bytes()).unwrap();
+        assert_eq!(decoded.msg_type(), MsgType::UserDefined(0x1234));
+        hdr.raw_type(3);
+        assert_eq!(hdr.msg_type(), MsgType::Done);
+    }
+
+    #[test]
+    fn test_get_flags() {
+        assert_eq!(NlMsgHeader::request().root().flags, 0x0101);
+        assert_eq!(NlMsgHeader::request().match_().flags, 0x0201);
+        assert_eq!(NlMsgHeader::request().root().match_().flags, 0x0301);
+        assert_eq!(NlMsgHeader::request().dump().flags, 0x0301);
+    }
+
+    #[test]
+    fn test_done_is_multipart() {
+        let done = NlMsgHeader::done();
+        assert_eq!(done.msg_type(), MsgType::Done);
+        assert_eq!(done.msg_flags(), 0x2);
+
+        let (decoded, _) = NlMsgHeader::from_bytes(done.bytes()).unwrap();
+        assert_eq!(decoded.flags_description(), "MULTI");
+    }
+
+    #[test]
+    fn test_echo_flag() {
+        let mut hdr = NlMsgHeader::request();
+        assert_eq!(hdr.echo().flags, 0x9);
+        assert_eq!(hdr.flags_description(), "REQUEST|ECHO");
+    }
+
+    #[test]
+    fn test_flags_description() {
+        assert_eq!(
+            NlMsgHeader::request().dump().flags_description(),
+            "REQUEST|DUMP"
+        );
+        assert_eq!(
+            NlMsgHeader::request().ack().atomic().flags_description(),
+            "REQUEST|ACK|ATOMIC"
+        );
+        assert_eq!(NlMsgHeader::error().capped().flags_description(), "CAPPED");
+        assert_eq!(
+            NlMsgHeader::done().multipart().append().flags_description(),
+            "MULTI|0x800"
+        );
+        let (hdr, _) = NlMsgHeader::from_bytes(&header_bytes(16, 20, 0, 0, 0)).unwrap();
+        assert_eq!(hdr.flags_description(), "0x0");
+    }
+
+    #[test]
+    fn test_encoding_field_order() {
+        // distinct bytes in every position catch swapped or misaligned fields
+        let expected = header_bytes(0x0403_0201, 0x0605, 0x0807, 0x0c0b_0a09, 0x100f_0e0d);
+        let (hdr, _) = NlMsgHeader::from_bytes(&expected).unwrap();
+
+        assert_eq!(hdr.msg_length(), 0x0403_0201);
+        assert_eq!(hdr.bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn test_msg_type_min_type_boundary() {
+        assert_eq!(MsgType::from(1), MsgType::Noop);
+        assert_eq!(MsgType::from(2), MsgType::Error);
+        assert_eq!(MsgType::from(3), MsgType::Done);
+        assert_eq!(MsgType::from(4), MsgType::Overrun);
+        assert!(MsgType::from(4).is_control());
+        // 10 is not special, 0x10 is the first protocol type
+        assert_eq!(MsgType::from(10), MsgType::UserDefined(10));
+        assert!(MsgType::from(15).is_control());
+        assert_eq!(MsgType::from(16), MsgType::UserDefined(16));
+        assert!(!MsgType::from(16).is_control());
+        assert_eq!(u16::from(MsgType::from(NLMSG_MIN_TYPE)), NLMSG_MIN_TYPE);
+    }
+
+    #[test]
+    fn test_msg_type_name() {
+        assert_eq!(MsgType::from(16).name(Protocol::Route), "RTM_NEWLINK");
+        assert_eq!(MsgType::from(26).name(Protocol::Route), "RTM_GETROUTE");
+        assert_eq!(MsgType::from(16).name(Protocol::Generic), "GENL_ID_CTRL");
+        assert_eq!(MsgType::from(16).name(Protocol::Usersock), "UNKNOWN");
+        assert_eq!(MsgType::from(1000).name(Protocol::Route), "UNKNOWN");
+        // control messages are named the same under every protocol
+        assert_eq!(MsgType::Error.name(Protocol::Route), "NLMSG_ERROR");
+        assert_eq!(MsgType::Done.name(Protocol::Generic), "NLMSG_DONE");
     }
 
     #[test]
     fn test_decoding() {
-        // Little endian only right now
-        let bytes = [16, 0, 0, 0, 0, 0, 1, 3, 1, 0, 0, 0, 9, 0, 0, 0, 1, 1, 1];
+        let mut bytes = header_bytes(16, 0, 0x0301, 1, 9);
+        // trailing bytes are not part of the header
+        bytes.extend_from_slice(&[1, 1, 1]);
         let mut h = NlMsgHeader::request();
         let expected = h.data_length(0).pid(9).seq(1).dump();
 
@@ -423,7 +775,7 @@ mod tests {
 
     #[test]
     fn test_decoding_error() {
-        // Little endian only right now
+        // a zero length is invalid in either byte order
         let bytes = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
         let res = NlMsgHeader::from_bytes(&bytes);
         assert!(res.is_err());
@@ -0,0 +1,192 @@
+use libc::{sysconf, _SC_PAGESIZE, ENOPROTOOPT, SOL_NETLINK};
+use socket::socket_impl::Socket as SocketImpl;
+use socket::NetlinkAddr;
+use std::io::{self, ErrorKind};
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// from linux/netlink.h, the ring options were removed in Linux 4.7
+const NETLINK_RX_RING: i32 = 6;
+const NETLINK_TX_RING: i32 = 7;
+
+// enum nl_mmap_status
+pub const NL_MMAP_STATUS_UNUSED: u32 = 0;
+pub const NL_MMAP_STATUS_VALID: u32 = 2;
+pub const NL_MMAP_STATUS_COPY: u32 = 3;
+pub const NL_MMAP_STATUS_SKIP: u32 = 4;
+
+// struct nl_mmap_req {
+//     unsigned int nm_block_size;
+//     unsigned int nm_block_nr;
+//     unsigned int nm_frame_size;
+//     unsigned int nm_frame_nr;
+// };
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct NlMmapReq {
+    block_size: u32,
+    block_nr: u32,
+    frame_size: u32,
+    frame_nr: u32,
+}
+
+// struct nl_mmap_hdr {
+//     unsigned int nm_status;
+//     unsigned int nm_len;
+//     __u32        nm_group;
+//     __u32        nm_pid;
+//     __u32        nm_uid;
+//     __u32        nm_gid;
+// };
+const NL_MMAP_HDRLEN: usize = 24;
+
+/// Whether the kernel knows `NETLINK_TX_RING`. An empty request tears
+/// down a ring, which is a no-op on a socket without one, so it probes for
+/// the option without mapping anything. Only ENOPROTOOPT means the option
+/// is missing; other errors, such as EPERM for an unprivileged caller,
+/// come from a kernel that has it.
+pub fn tx_ring_supported(socket: &SocketImpl) -> bool {
+    let req = NlMmapReq {
+        block_size: 0,
+        block_nr: 0,
+        frame_size: 0,
+        frame_nr: 0,
+    };
+    match socket.setsockopt(SOL_NETLINK, NETLINK_TX_RING, req) {
+        Ok(()) => true,
+        Err(e) => e.raw_os_error() != Some(ENOPROTOOPT),
+    }
+}
+
+/// Offset of frame `i` in the mapping. Frames do not cross blocks, so the
+/// tail of a block that does not hold a whole number of them is unused.
+fn frame_offset(i: usize, block_size: usize, per_block: usize, frame_size: usize) -> usize {
+    (i / per_block) * block_size + (i % per_block) * frame_size
+}
+
+/// A memory mapped receive ring, see `Socket::enable_rx_ring`
+#[derive(Debug)]
+pub struct RxRing {
+    base: *mut u8,
+    len: usize,
+    block_size: usize,
+    per_block: usize,
+    frame_size: usize,
+    frame_nr: usize,
+    head: usize,
+}
+
+// the mapping belongs to the ring alone, the kernel side is synchronized
+// through the frame status words
+unsafe impl Send for RxRing {}
+
+impl RxRing {
+    /// Sets up a ring of at least `frame_nr` frames of `frame_size` bytes.
+    /// Each block is `frame_size` rounded up to the page size, so the frame
+    /// count is rounded up to fill the last block.
+    pub fn new(socket: &SocketImpl, frame_size: usize, frame_nr: usize) -> io::Result<RxRing> {
+        if frame_size <= NL_MMAP_HDRLEN || !frame_size.is_multiple_of(4) || frame_nr == 0 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "frame size must be a multiple of 4 above the frame header",
+            ));
+        }
+        let page = unsafe { sysconf(_SC_PAGESIZE) } as usize;
+        let block_size = frame_size.div_ceil(page) * page;
+        let per_block = block_size / frame_size;
+        let block_nr = frame_nr.div_ceil(per_block);
+        let req = NlMmapReq {
+            block_size: block_size as u32,
+            block_nr: block_nr as u32,
+            frame_size: frame_size as u32,
+            frame_nr: (block_nr * per_block) as u32,
+        };
+
+        if let Err(e) = socket.setsockopt(SOL_NETLINK, NETLINK_RX_RING, req) {
+            return Err(match e.raw_os_error() {
+                Some(ENOPROTOOPT) => io::Error::new(
+                    ErrorKind::Unsupported,
+                    "the kernel has no netlink mmap support, it was removed in Linux 4.7",
+                ),
+                _ => e,
+            });
+        }
+        let len = block_size * block_nr;
+        let base = socket.mmap(len)?;
+        Ok(RxRing {
+            base,
+            len,
+            block_size,
+            per_block,
+            frame_size,
+            frame_nr: req.frame_nr as usize,
+            head: 0,
+        })
+    }
+
+    fn frame(&self) -> *mut u8 {
+        let offset = frame_offset(self.head, self.block_size, self.per_block, self.frame_size);
+        unsafe { self.base.add(offset) }
+    }
+
+    fn status_word(&self) -> &AtomicU32 {
+        unsafe { &*(self.frame() as *const AtomicU32) }
+    }
+
+    /// `nm_status` of the frame at the head of the ring
+    pub fn status(&self) -> u32 {
+        self.status_word().load(Ordering::Acquire)
+    }
+
+    /// Sender and message bytes of the head frame, valid while its status
+    /// is `NL_MMAP_STATUS_VALID`
+    pub fn data(&self) -> (NetlinkAddr, &[u8]) {
+        unsafe {
+            let words = self.frame() as *const u32;
+            let len = (*words.add(1) as usize).min(self.frame_size - NL_MMAP_HDRLEN);
+            // nm_group is a group number, the address carries a mask
+            let group = *words.add(2);
+            let groups = if (1..=32).contains(&group) {
+                1 << (group - 1)
+            } else {
+                0
+            };
+            let addr = NetlinkAddr::new(*words.add(3), groups);
+            let data = slice::from_raw_parts(self.frame().add(NL_MMAP_HDRLEN), len);
+            (addr, data)
+        }
+    }
+
+    /// Hands the head frame back to the kernel and moves to the next one
+    pub fn release(&mut self) {
+        self.status_word()
+            .store(NL_MMAP_STATUS_UNUSED, Ordering::Release);
+        self.head = (self.head + 1) % self.frame_nr;
+    }
+}
+
+impl Drop for RxRing {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.base as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_offset() {
+        // one 3000 byte frame per 4 KiB block
+        assert_eq!(frame_offset(0, 4096, 1, 3000), 0);
+        assert_eq!(frame_offset(1, 4096, 1, 3000), 4096);
+        assert_eq!(frame_offset(2, 4096, 1, 3000), 8192);
+
+        // five 800 byte frames per block, 96 bytes unused at its end
+        assert_eq!(frame_offset(4, 4096, 5, 800), 3200);
+        assert_eq!(frame_offset(5, 4096, 5, 800), 4096);
+        assert_eq!(frame_offset(7, 4096, 5, 800), 5696);
+    }
+}
@@ -3,15 +3,18 @@
 #![allow(unused_unsafe)]
 
 use libc::{
-    accept, bind, c_void, close, connect, getsockname, iovec, listen, msghdr, recv, recvfrom, send,
-    sendmsg, sendto, setsockopt, shutdown, size_t, sockaddr, socket, socklen_t,
+    accept, bind, c_int, c_void, close, connect, fcntl, getsockname, getsockopt, iovec, listen,
+    msghdr, poll, pollfd, recv, recvfrom, recvmsg, send, sendmsg, sendto, setsockopt, shutdown,
+    size_t, sockaddr, socket, socklen_t, EBADF, EINTR, FD_CLOEXEC, F_GETFD, F_SETFD, POLLIN,
 };
-use std::io::{Error, Result};
-use std::iter::repeat;
+use libc::{ioctl, mmap, Ioctl, MAP_FAILED, MAP_SHARED, PROT_READ, PROT_WRITE};
+use libc::{CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR};
+use std::cell::Cell;
+use std::io::{Error, ErrorKind, IoSlice, IoSliceMut, Result};
 use std::mem;
 use std::ops::Drop;
 use std::ptr;
-use std::vec::Vec;
+use std::time::Duration;
 
 macro_rules! _try {
     ( $x:expr ) => {{
@@ -23,6 +26,19 @@ macro_rules! _try {
     }};
 }
 
+// The length of a received datagram. Netlink never delivers empty ones,
+// and a read of 0 bytes would otherwise pass for a datagram without
+// messages.
+fn datagram_len(received: isize) -> Result<usize> {
+    if received == 0 {
+        return Err(Error::new(
+            ErrorKind::UnexpectedEof,
+            "received an empty datagram",
+        ));
+    }
+    Ok(received as usize)
+}
+
 fn sockaddr_len() -> socklen_t {
     let struct_size = mem::size_of::<sockaddr>();
     let v = struct_size as socklen_t;
@@ -32,29 +48,69 @@ fn sockaddr_len() -> socklen_t {
 
 #[derive(Debug)]
 pub struct Socket {
-    fd: i32,
+    /// -1 once closed
+    fd: Cell<i32>,
 }
 
 impl Socket {
     pub fn new(family: i32, socket_type: i32, protocol: i32) -> Result<Socket> {
         let fd = _try!(socket(family, socket_type, protocol));
-        Ok(Socket { fd })
+        Ok(Socket { fd: Cell::new(fd) })
+    }
+
+    /// Takes ownership of the open descriptor `fd`
+    pub fn from_fd(fd: c_int) -> Socket {
+        Socket { fd: Cell::new(fd) }
+    }
+
+    /// Gives up ownership of the descriptor, failing if it was closed
+    pub fn into_fd(self) -> Result<c_int> {
+        let fd = self.open_fd()?;
+        self.fd.set(-1);
+        Ok(fd)
+    }
+
+    // The descriptor for sending or receiving, failing with NotConnected
+    // rather than EBADF once closed
+    fn open_fd(&self) -> Result<c_int> {
+        match self.fd.get() {
+            -1 => Err(Error::new(ErrorKind::NotConnected, "socket is closed")),
+            fd => Ok(fd),
+        }
     }
 
     /// Returns the underlying file descriptor.
     pub fn fileno(&self) -> i32 {
-        self.fd
+        self.fd.get()
+    }
+
+    /// Sets or clears `FD_CLOEXEC` on the descriptor
+    pub fn set_cloexec(&self, on: bool) -> Result<()> {
+        let flags = _try!(fcntl(self.fd.get(), F_GETFD));
+        let flags = if on {
+            flags | FD_CLOEXEC
+        } else {
+            flags & !FD_CLOEXEC
+        };
+        _try!(fcntl(self.fd.get(), F_SETFD, flags));
+        Ok(())
+    }
+
+    pub fn cloexec(&self) -> Result<bool> {
+        let flags = _try!(fcntl(self.fd.get(), F_GETFD));
+        Ok(flags & FD_CLOEXEC != 0)
     }
 
     pub fn getsockname(&self) -> Result<sockaddr> {
         let mut sa: sockaddr = unsafe { mem::zeroed() };
         let mut len: socklen_t = mem::size_of::<sockaddr>() as socklen_t;
         _try!(getsockname(
-            self.fd,
+            self.fd.get(),
             &mut sa as *mut sockaddr,
             &mut len as *mut socklen_t
         ));
-        assert_eq!(len, mem::size_of::<sockaddr>() as socklen_t);
+        // sockaddr_nl only has 12 bytes, still fits into 16 byte sockaddr
+        assert!(len <= mem::size_of::<sockaddr>() as socklen_t);
 
         Ok(sa)
     }
@@ -62,20 +118,103 @@ impl Socket {
     pub fn setsockopt<T>(&self, level: i32, name: i32, value: T) -> Result<()> {
         unsafe {
             let value = &value as *const T as *const c_void;
-            _try!(setsockopt(self.fd, level, name, value, sockaddr_len()));
+            _try!(setsockopt(
+                self.fd.get(),
+                level,
+                name,
+                value,
+                mem::size_of::<T>() as socklen_t
+            ));
         }
         Ok(())
     }
 
+    pub fn getsockopt<T: Copy>(&self, level: i32, name: i32) -> Result<T> {
+        let mut value: T = unsafe { mem::zeroed() };
+        let mut len = mem::size_of::<T>() as socklen_t;
+        _try!(getsockopt(
+            self.fd.get(),
+            level,
+            name,
+            &mut value as *mut T as *mut c_void,
+            &mut len as *mut socklen_t
+        ));
+        Ok(value)
+    }
+
+    /// Reads an option of variable size into `buffer`, returning the size
+    /// the kernel reports, which may exceed `buffer.len()`.
+    pub fn getsockopt_into(&self, level: i32, name: i32, buffer: &mut [u8]) -> Result<usize> {
+        let mut len = buffer.len() as socklen_t;
+        _try!(getsockopt(
+            self.fd.get(),
+            level,
+            name,
+            buffer.as_mut_ptr() as *mut c_void,
+            &mut len as *mut socklen_t
+        ));
+        Ok(len as usize)
+    }
+
+    /// Waits up to `timeout` for the socket to become readable, returning
+    /// false if it did not.
+    pub fn poll_in(&self, timeout: Duration) -> Result<bool> {
+        // round up so a sub-millisecond remainder does not spin
+        let ms = timeout.as_nanos().div_ceil(1_000_000);
+        self.poll_readable(ms.min(c_int::MAX as u128) as c_int)
+    }
+
+    /// Waits without a timeout for the socket to become readable
+    pub fn wait_in(&self) -> Result<()> {
+        self.poll_readable(-1).map(|_| ())
+    }
+
+    fn poll_readable(&self, timeout_ms: c_int) -> Result<bool> {
+        let mut fds = pollfd {
+            fd: self.fd.get(),
+            events: POLLIN,
+            revents: 0,
+        };
+        let ready = _try!(poll(&mut fds, 1, timeout_ms));
+        Ok(ready > 0)
+    }
+
+    /// Issues an ioctl whose result is an int written through its argument
+    pub fn ioctl_int(&self, request: Ioctl) -> Result<c_int> {
+        let mut value: c_int = 0;
+        _try!(ioctl(self.fd.get(), request, &mut value as *mut c_int));
+        Ok(value)
+    }
+
+    /// Maps `len` bytes of the socket's rings, read and write, shared with
+    /// the kernel
+    pub fn mmap(&self, len: usize) -> Result<*mut u8> {
+        let base = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED,
+                self.fd.get(),
+                0,
+            )
+        };
+        if base == MAP_FAILED {
+            return Err(Error::last_os_error());
+        }
+        Ok(base as *mut u8)
+    }
+
     /// Binds socket to an address
     pub fn bind(&self, address: &sockaddr) -> Result<()> {
-        _try!(bind(self.fd, address, sockaddr_len()));
+        _try!(bind(self.fd.get(), address, sockaddr_len()));
         Ok(())
     }
 
     pub fn sendto(&self, buffer: &[u8], flags: i32, sa: &sockaddr) -> Result<usize> {
+        let fd = self.open_fd()?;
         let sent = _try!(sendto(
-            self.fd,
+            fd,
             buffer.as_ptr() as *const c_void,
             buffer.len() as size_t,
             flags,
@@ -86,8 +225,9 @@ impl Socket {
     }
 
     pub fn send(&self, buffer: &[u8], flags: i32) -> Result<usize> {
+        let fd = self.open_fd()?;
         let sent = _try!(send(
-            self.fd,
+            fd,
             buffer.as_ptr() as *const c_void,
             buffer.len() as size_t,
             flags
@@ -96,6 +236,7 @@ impl Socket {
     }
 
     pub fn sendmsg(&self, msg: &[u8], data: &[u8], flags: i32, sa: &sockaddr) -> Result<usize> {
+        let fd = self.open_fd()?;
         let msg = unsafe {
             let msg_iovec = iovec {
                 iov_base: msg as *const [u8] as *mut c_void,
@@ -109,7 +250,7 @@ impl Socket {
             msghdr {
                 msg_name: sa as *const sockaddr as *mut c_void,
                 msg_namelen: sockaddr_len(),
-                msg_iov: iovecs.as_mut_ptr() as *mut iovec,
+                msg_iov: iovecs.as_mut_ptr(),
                 msg_iovlen: 2,
                 msg_control: ptr::null_mut(),
                 msg_controllen: 0,
@@ -117,16 +258,100 @@ impl Socket {
             }
         };
 
-        let sent = _try!(sendmsg(self.fd, &msg as *const msghdr, flags));
+        let sent = _try!(sendmsg(fd, &msg as *const msghdr, flags));
+        Ok(sent as usize)
+    }
+
+    /// Sends the concatenation of `bufs` as one datagram
+    pub fn sendmsg_vectored(&self, bufs: &[IoSlice], flags: i32, sa: &sockaddr) -> Result<usize> {
+        let fd = self.open_fd()?;
+        // IoSlice is guaranteed to be ABI compatible with iovec
+        let msg = msghdr {
+            msg_name: sa as *const sockaddr as *mut c_void,
+            msg_namelen: sockaddr_len(),
+            msg_iov: bufs.as_ptr() as *mut iovec,
+            msg_iovlen: bufs.len() as _,
+            msg_control: ptr::null_mut(),
+            msg_controllen: 0,
+            msg_flags: 0,
+        };
+
+        let sent = _try!(sendmsg(fd, &msg as *const msghdr, flags));
         Ok(sent as usize)
     }
 
+    /// Receives one datagram scattered over `bufs`, returning the sender and
+    /// the number of bytes read.
+    pub fn recvmsg_vectored(
+        &self,
+        bufs: &mut [IoSliceMut],
+        flags: i32,
+    ) -> Result<(sockaddr, usize)> {
+        let fd = self.open_fd()?;
+        let mut sa: sockaddr = unsafe { mem::zeroed() };
+        let mut msg = msghdr {
+            msg_name: &mut sa as *mut sockaddr as *mut c_void,
+            msg_namelen: sockaddr_len(),
+            msg_iov: bufs.as_mut_ptr() as *mut iovec,
+            msg_iovlen: bufs.len() as _,
+            msg_control: ptr::null_mut(),
+            msg_controllen: 0,
+            msg_flags: 0,
+        };
+
+        let received = _try!(recvmsg(fd, &mut msg as *mut msghdr, flags));
+        // sockaddr_nl only has 12 bytes, still fits into 16 byte sockaddr
+        assert!(msg.msg_namelen <= sockaddr_len());
+        Ok((sa, datagram_len(received)?))
+    }
+
+    /// Like `recvfrom_into`, also returning the `c_int` value of the first
+    /// control message at `level` and `ty`, if one came along
+    pub fn recvmsg_cmsg_int(
+        &self,
+        buffer: &mut [u8],
+        flags: i32,
+        level: c_int,
+        ty: c_int,
+    ) -> Result<(sockaddr, usize, Option<c_int>)> {
+        let fd = self.open_fd()?;
+        let mut sa: sockaddr = unsafe { mem::zeroed() };
+        let mut iov = iovec {
+            iov_base: buffer.as_mut_ptr() as *mut c_void,
+            iov_len: buffer.len(),
+        };
+        // room for a few control messages, u64 keeps it aligned for cmsghdr
+        let mut control = [0u64; 8];
+        let mut msg = msghdr {
+            msg_name: &mut sa as *mut sockaddr as *mut c_void,
+            msg_namelen: sockaddr_len(),
+            msg_iov: &mut iov,
+            msg_iovlen: 1,
+            msg_control: control.as_mut_ptr() as *mut c_void,
+            msg_controllen: mem::size_of_val(&control) as _,
+            msg_flags: 0,
+        };
+
+        let received = _try!(recvmsg(fd, &mut msg as *mut msghdr, flags));
+        assert!(msg.msg_namelen <= sockaddr_len());
+
+        let mut value = None;
+        unsafe {
+            let mut cmsg = CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == level && (*cmsg).cmsg_type == ty {
+                    value = Some(ptr::read_unaligned(CMSG_DATA(cmsg) as *const c_int));
+                    break;
+                }
+                cmsg = CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        Ok((sa, datagram_len(received)?, value))
+    }
+
     /// Receives data from a remote socket and returns it with the address of the socket.
     pub fn recvfrom(&self, bytes: usize, flags: i32) -> Result<(sockaddr, Box<[u8]>)> {
-        let mut a = Vec::with_capacity(bytes);
-
-        // This is needed to get some actual elements in the vector, not just a capacity
-        a.extend(repeat(0u8).take(bytes));
+        let mut a = vec![0u8; bytes];
 
         let (socket_addr, received) = self.recvfrom_into(&mut a[..], flags)?;
 
@@ -137,11 +362,12 @@ impl Socket {
     /// Similar to `recvfrom` but receives to predefined buffer and returns the number
     /// of bytes read.
     pub fn recvfrom_into(&self, buffer: &mut [u8], flags: i32) -> Result<(sockaddr, usize)> {
+        let fd = self.open_fd()?;
         let mut sa: sockaddr = unsafe { mem::zeroed() };
         let sockaddr_len = sockaddr_len();
         let mut sa_len: socklen_t = sockaddr_len;
         let received = _try!(recvfrom(
-            self.fd,
+            fd,
             buffer.as_ptr() as *mut c_void,
             buffer.len() as size_t,
             flags,
@@ -150,15 +376,12 @@ impl Socket {
         ));
         // sockaddr_nl only has 12 bytes, still fits into 16 byte sockaddr
         assert!(sa_len <= sockaddr_len);
-        Ok((sa, received as usize))
+        Ok((sa, datagram_len(received)?))
     }
 
     /// Returns up to `bytes` bytes received from the remote socket.
     pub fn recv(&self, bytes: usize, flags: i32) -> Result<Box<[u8]>> {
-        let mut a = Vec::with_capacity(bytes);
-
-        // This is needed to get some actual elements in the vector, not just a capacity
-        a.extend(repeat(0u8).take(bytes));
+        let mut a = vec![0u8; bytes];
 
         let received = self.recv_into(&mut a[..], flags)?;
 
@@ -169,22 +392,27 @@ impl Socket {
     /// Similar to `recv` but receives to predefined buffer and returns the number
     /// of bytes read.
     pub fn recv_into(&self, buffer: &mut [u8], flags: i32) -> Result<usize> {
+        let fd = self.open_fd()?;
         let received = _try!(recv(
-            self.fd,
+            fd,
             buffer.as_ptr() as *mut c_void,
             buffer.len() as size_t,
             flags
         ));
-        Ok(received as usize)
+        datagram_len(received)
     }
 
     pub fn connect(&self, address: &sockaddr) -> Result<()> {
-        _try!(connect(self.fd, address as *const sockaddr, sockaddr_len()));
+        _try!(connect(
+            self.fd.get(),
+            address as *const sockaddr,
+            sockaddr_len()
+        ));
         Ok(())
     }
 
     pub fn listen(&self, backlog: i32) -> Result<()> {
-        _try!(listen(self.fd, backlog));
+        _try!(listen(self.fd.get(), backlog));
         Ok(())
     }
 
@@ -194,28 +422,46 @@ impl Socket {
         let mut sa_len: socklen_t = sockaddr_len;
 
         let fd = _try!(accept(
-            self.fd,
+            self.fd.get(),
             &mut sa as *mut sockaddr,
             &mut sa_len as *mut socklen_t
         ));
         assert_eq!(sa_len, sockaddr_len);
-        Ok((Socket { fd }, sa))
+        Ok((Socket { fd: Cell::new(fd) }, sa))
     }
 
+    /// Closes the descriptor, at most once.
+    ///
+    /// Linux releases the descriptor even when close(2) fails with EINTR, so
+    /// that is reported as success: retrying could close an unrelated
+    /// descriptor that reused the number. Closing an already closed socket
+    /// returns EBADF without calling close(2) again.
     pub fn close(&self) -> Result<()> {
-        _try!(close(self.fd));
+        let fd = self.fd.replace(-1);
+        if fd == -1 {
+            return Err(Error::from_raw_os_error(EBADF));
+        }
+
+        if unsafe { close(fd) } == -1 {
+            let err = Error::last_os_error();
+            if err.raw_os_error() != Some(EINTR) {
+                return Err(err);
+            }
+        }
         Ok(())
     }
 
     pub fn shutdown(&self, how: i32) -> Result<()> {
-        _try!(shutdown(self.fd, how));
+        _try!(shutdown(self.fd.get(), how));
         Ok(())
     }
 }
 
 impl Drop for Socket {
     fn drop(&mut self) {
-        let _ = self.close();
+        if self.fd.get() != -1 {
+            let _ = self.close();
+        }
     }
 }
 
@@ -260,6 +506,25 @@ mod tests {
         Socket::new(AF_NETLINK, SOCK_RAW, 0).unwrap();
     }
 
+    #[test]
+    fn close_twice_is_ebadf() {
+        let socket = Socket::new(AF_NETLINK, SOCK_RAW, 0).unwrap();
+        socket.close().unwrap();
+        assert_eq!(socket.fileno(), -1);
+        let err = socket.close().unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(EBADF));
+    }
+
+    #[test]
+    fn set_cloexec_works() {
+        let socket = Socket::new(AF_NETLINK, SOCK_RAW, 0).unwrap();
+        assert!(!socket.cloexec().unwrap());
+        socket.set_cloexec(true).unwrap();
+        assert!(socket.cloexec().unwrap());
+        socket.set_cloexec(false).unwrap();
+        assert!(!socket.cloexec().unwrap());
+    }
+
     #[test]
     fn some_basic_socket_stuff_works() {
         let socket = Socket::new(AF_INET, SOCK_DGRAM, 0).unwrap();
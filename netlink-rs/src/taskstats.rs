@@ -0,0 +1,404 @@
+use byteorder::{NativeEndian, ReadBytesExt};
+use genetlink::GenlMsgHeader;
+use socket::{AttrBuilder, AttrIter, Msg, NetlinkAddr, NlMsgHeader, Payload, Socket};
+use std::io::{self, Cursor, ErrorKind, Seek, SeekFrom};
+use std::str;
+
+/// Name to resolve the family id with `genetlink::resolve_family`
+pub const TASKSTATS_GENL_NAME: &str = "TASKSTATS";
+pub const TASKSTATS_GENL_VERSION: u8 = 1;
+
+// Commands, from linux/taskstats.h
+pub const TASKSTATS_CMD_UNSPEC: u8 = 0;
+/// user->kernel request/get-response
+pub const TASKSTATS_CMD_GET: u8 = 1;
+/// kernel->user event
+pub const TASKSTATS_CMD_NEW: u8 = 2;
+
+// Request attributes
+pub const TASKSTATS_CMD_ATTR_UNSPEC: u16 = 0;
+pub const TASKSTATS_CMD_ATTR_PID: u16 = 1;
+pub const TASKSTATS_CMD_ATTR_TGID: u16 = 2;
+pub const TASKSTATS_CMD_ATTR_REGISTER_CPUMASK: u16 = 3;
+pub const TASKSTATS_CMD_ATTR_DEREGISTER_CPUMASK: u16 = 4;
+
+// Reply attributes
+pub const TASKSTATS_TYPE_UNSPEC: u16 = 0;
+/// Process id
+pub const TASKSTATS_TYPE_PID: u16 = 1;
+/// Thread group id
+pub const TASKSTATS_TYPE_TGID: u16 = 2;
+/// taskstats structure
+pub const TASKSTATS_TYPE_STATS: u16 = 3;
+/// contains pid + stats
+pub const TASKSTATS_TYPE_AGGR_PID: u16 = 4;
+/// contains tgid + stats
+pub const TASKSTATS_TYPE_AGGR_TGID: u16 = 5;
+/// contains nothing
+pub const TASKSTATS_TYPE_NULL: u16 = 6;
+
+// #define TS_COMM_LEN     32
+const TS_COMM_LEN: usize = 32;
+
+// offsets of the fields decoded below, which every version of struct
+// taskstats starts with; several members are aligned(8)
+const COMM_OFFSET: usize = 80;
+const SCHED_OFFSET: usize = 112;
+const UID_OFFSET: usize = 120;
+const ETIME_OFFSET: usize = 144;
+const PREFIX_LEN: usize = 184;
+
+/// The leading, version independent fields of `struct taskstats`. Delays
+/// are in nanoseconds, times in microseconds.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TaskStats {
+    version: u16,
+    exitcode: u32,
+    flag: u8,
+    nice: u8,
+    cpu_count: u64,
+    cpu_delay_total: u64,
+    blkio_count: u64,
+    blkio_delay_total: u64,
+    swapin_count: u64,
+    swapin_delay_total: u64,
+    cpu_run_real_total: u64,
+    cpu_run_virtual_total: u64,
+    comm: String,
+    sched: u8,
+    uid: u32,
+    gid: u32,
+    pid: u32,
+    ppid: u32,
+    btime: u32,
+    etime: u64,
+    utime: u64,
+    stime: u64,
+    minflt: u64,
+    majflt: u64,
+}
+
+impl TaskStats {
+    /// Decodes the value of a `TASKSTATS_TYPE_STATS` attribute
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<TaskStats> {
+        if bytes.len() < PREFIX_LEN {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "taskstats structure too short",
+            ));
+        }
+
+        let mut cursor = Cursor::new(bytes);
+        let version = cursor.read_u16::<NativeEndian>()?;
+        cursor.seek(SeekFrom::Start(4))?;
+        let exitcode = cursor.read_u32::<NativeEndian>()?;
+        let flag = cursor.read_u8()?;
+        let nice = cursor.read_u8()?;
+        cursor.seek(SeekFrom::Start(16))?;
+        let cpu_count = cursor.read_u64::<NativeEndian>()?;
+        let cpu_delay_total = cursor.read_u64::<NativeEndian>()?;
+        let blkio_count = cursor.read_u64::<NativeEndian>()?;
+        let blkio_delay_total = cursor.read_u64::<NativeEndian>()?;
+        let swapin_count = cursor.read_u64::<NativeEndian>()?;
+        let swapin_delay_total = cursor.read_u64::<NativeEndian>()?;
+        let cpu_run_real_total = cursor.read_u64::<NativeEndian>()?;
+        let cpu_run_virtual_total = cursor.read_u64::<NativeEndian>()?;
+
+        let comm = &bytes[COMM_OFFSET..COMM_OFFSET + TS_COMM_LEN];
+        let comm = match comm.iter().position(|&b| b == 0) {
+            Some(n) => &comm[..n],
+            None => comm,
+        };
+        let comm = str::from_utf8(comm)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?
+            .to_owned();
+
+        let sched = bytes[SCHED_OFFSET];
+        cursor.seek(SeekFrom::Start(UID_OFFSET as u64))?;
+        let uid = cursor.read_u32::<NativeEndian>()?;
+        let gid = cursor.read_u32::<NativeEndian>()?;
+        let pid = cursor.read_u32::<NativeEndian>()?;
+        let ppid = cursor.read_u32::<NativeEndian>()?;
+        let btime = cursor.read_u32::<NativeEndian>()?;
+        cursor.seek(SeekFrom::Start(ETIME_OFFSET as u64))?;
+        let etime = cursor.read_u64::<NativeEndian>()?;
+        let utime = cursor.read_u64::<NativeEndian>()?;
+        let stime = cursor.read_u64::<NativeEndian>()?;
+        let minflt = cursor.read_u64::<NativeEndian>()?;
+        let majflt = cursor.read_u64::<NativeEndian>()?;
+
+        Ok(TaskStats {
+            version,
+            exitcode,
+            flag,
+            nice,
+            cpu_count,
+            cpu_delay_total,
+            blkio_count,
+            blkio_delay_total,
+            swapin_count,
+            swapin_delay_total,
+            cpu_run_real_total,
+            cpu_run_virtual_total,
+            comm,
+            sched,
+            uid,
+            gid,
+            pid,
+            ppid,
+            btime,
+            etime,
+            utime,
+            stime,
+            minflt,
+            majflt,
+        })
+    }
+
+    /// Decodes the payload of a `TASKSTATS_CMD_NEW` reply, which nests the
+    /// structure inside a `TASKSTATS_TYPE_AGGR_PID` or `_AGGR_TGID` attribute
+    pub fn from_reply(bytes: &[u8]) -> io::Result<TaskStats> {
+        let (_, n) = GenlMsgHeader::from_bytes(bytes)?;
+        for attr in AttrIter::new(&bytes[n..]) {
+            let attr = attr?;
+            if attr.kind() != TASKSTATS_TYPE_AGGR_PID && attr.kind() != TASKSTATS_TYPE_AGGR_TGID {
+                continue;
+            }
+            for inner in attr.nested() {
+                let inner = inner?;
+                if inner.kind() == TASKSTATS_TYPE_STATS {
+                    return TaskStats::from_bytes(inner.value());
+                }
+            }
+        }
+
+        Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "reply without taskstats structure",
+        ))
+    }
+
+    /// Version of the structure the kernel filled in
+    pub fn version(&self) -> u16 {
+        self.version
+    }
+
+    /// Exit status
+    pub fn exitcode(&self) -> u32 {
+        self.exitcode
+    }
+
+    /// Accounting flags, AFORK etc.
+    pub fn flag(&self) -> u8 {
+        self.flag
+    }
+
+    pub fn nice(&self) -> u8 {
+        self.nice
+    }
+
+    pub fn cpu_count(&self) -> u64 {
+        self.cpu_count
+    }
+
+    pub fn cpu_delay_total(&self) -> u64 {
+        self.cpu_delay_total
+    }
+
+    pub fn blkio_count(&self) -> u64 {
+        self.blkio_count
+    }
+
+    pub fn blkio_delay_total(&self) -> u64 {
+        self.blkio_delay_total
+    }
+
+    pub fn swapin_count(&self) -> u64 {
+        self.swapin_count
+    }
+
+    pub fn swapin_delay_total(&self) -> u64 {
+        self.swapin_delay_total
+    }
+
+    pub fn cpu_run_real_total(&self) -> u64 {
+        self.cpu_run_real_total
+    }
+
+    pub fn cpu_run_virtual_total(&self) -> u64 {
+        self.cpu_run_virtual_total
+    }
+
+    /// Command name
+    pub fn comm(&self) -> &str {
+        &self.comm
+    }
+
+    /// Scheduling discipline
+    pub fn sched(&self) -> u8 {
+        self.sched
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn ppid(&self) -> u32 {
+        self.ppid
+    }
+
+    /// Begin time, seconds since the epoch
+    pub fn btime(&self) -> u32 {
+        self.btime
+    }
+
+    /// Elapsed time
+    pub fn etime(&self) -> u64 {
+        self.etime
+    }
+
+    /// User CPU time
+    pub fn utime(&self) -> u64 {
+        self.utime
+    }
+
+    /// System CPU time
+    pub fn stime(&self) -> u64 {
+        self.stime
+    }
+
+    /// Minor page fault count
+    pub fn minflt(&self) -> u64 {
+        self.minflt
+    }
+
+    /// Major page fault count
+    pub fn majflt(&self) -> u64 {
+        self.majflt
+    }
+}
+
+/// Payload of a `TASKSTATS_CMD_GET` request for the task `pid`
+pub fn pid_request(pid: u32) -> Vec<u8> {
+    let mut attrs = AttrBuilder::new();
+    attrs.put_u32(TASKSTATS_CMD_ATTR_PID, pid);
+
+    let mut payload: Vec<u8> = GenlMsgHeader::new(TASKSTATS_CMD_GET, TASKSTATS_GENL_VERSION)
+        .bytes()
+        .into();
+    payload.extend_from_slice(attrs.bytes());
+    payload
+}
+
+/// Requests the statistics of task `pid` from the taskstats family with id
+/// `family`
+pub fn query_pid(socket: &mut Socket, family: u16, pid: u32) -> io::Result<TaskStats> {
+    let payload = pid_request(pid);
+    let hdr = NlMsgHeader::user_defined(family, payload.len() as u32);
+    let msg = Msg::new(hdr, Payload::Data(&payload));
+
+    let (_, messages) = socket.send_and_recv(msg, &NetlinkAddr::new(0, 0))?;
+    for msg in messages {
+        match *msg.payload() {
+            Payload::Data(b) => return TaskStats::from_reply(b),
+            Payload::Err(errno, _) => return Err(io::Error::from_raw_os_error(-errno)),
+            _ => {}
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::InvalidData,
+        "no reply from taskstats",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+
+    fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+        (&mut bytes[offset..offset + 4])
+            .write_u32::<NativeEndian>(value)
+            .unwrap();
+    }
+
+    fn put_u64(bytes: &mut [u8], offset: usize, value: u64) {
+        (&mut bytes[offset..offset + 8])
+            .write_u64::<NativeEndian>(value)
+            .unwrap();
+    }
+
+    // start of a version 14 struct taskstats as the kernel lays it out
+    fn sample_stats() -> Vec<u8> {
+        let mut stats = vec![0u8; 416];
+        (&mut stats[..2]).write_u16::<NativeEndian>(14).unwrap();
+        put_u32(&mut stats, 4, 0);
+        stats[8] = 0x02; // ASU
+        stats[9] = 0;
+        put_u64(&mut stats, 16, 12);
+        put_u64(&mut stats, 24, 3400);
+        stats[COMM_OFFSET..COMM_OFFSET + 4].copy_from_slice(b"bash");
+        stats[SCHED_OFFSET] = 0;
+        put_u32(&mut stats, 120, 1000);
+        put_u32(&mut stats, 124, 100);
+        put_u32(&mut stats, 128, 4242);
+        put_u32(&mut stats, 132, 1);
+        put_u32(&mut stats, 136, 1_700_000_000);
+        put_u64(&mut stats, 144, 5_000_000);
+        put_u64(&mut stats, 152, 1500);
+        put_u64(&mut stats, 160, 700);
+        put_u64(&mut stats, 168, 321);
+        put_u64(&mut stats, 176, 2);
+        stats
+    }
+
+    #[test]
+    fn test_taskstats_decode() {
+        let mut inner = AttrBuilder::new();
+        inner
+            .put_u32(TASKSTATS_TYPE_PID, 4242)
+            .put(TASKSTATS_TYPE_STATS, &sample_stats());
+        let mut attrs = AttrBuilder::new();
+        attrs.put_nested(TASKSTATS_TYPE_AGGR_PID, &inner);
+        let mut reply: Vec<u8> = GenlMsgHeader::new(TASKSTATS_CMD_NEW, 1).bytes().into();
+        reply.extend_from_slice(attrs.bytes());
+
+        let stats = TaskStats::from_reply(&reply).unwrap();
+        assert_eq!(stats.version(), 14);
+        assert_eq!(stats.flag(), 0x02);
+        assert_eq!(stats.cpu_count(), 12);
+        assert_eq!(stats.cpu_delay_total(), 3400);
+        assert_eq!(stats.comm(), "bash");
+        assert_eq!(stats.uid(), 1000);
+        assert_eq!(stats.gid(), 100);
+        assert_eq!(stats.pid(), 4242);
+        assert_eq!(stats.ppid(), 1);
+        assert_eq!(stats.btime(), 1_700_000_000);
+        assert_eq!(stats.etime(), 5_000_000);
+        assert_eq!(stats.utime(), 1500);
+        assert_eq!(stats.stime(), 700);
+        assert_eq!(stats.minflt(), 321);
+        assert_eq!(stats.majflt(), 2);
+
+        assert!(TaskStats::from_bytes(&sample_stats()[..100]).is_err());
+    }
+
+    #[test]
+    fn test_pid_request() {
+        let payload = pid_request(4242);
+        let (hdr, n) = GenlMsgHeader::from_bytes(&payload).unwrap();
+        assert_eq!(hdr.cmd(), TASKSTATS_CMD_GET);
+        let attr = AttrIter::new(&payload[n..]).next().unwrap().unwrap();
+        assert_eq!(attr.ty(), TASKSTATS_CMD_ATTR_PID);
+        assert_eq!(attr.as_u32().unwrap(), 4242);
+    }
+}